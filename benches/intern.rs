@@ -0,0 +1,56 @@
+//! Measures how fast symbols can be interned by several threads sharing one `SymTable`, both
+//! when the symbols already exist (the usual case, when code is read) and when every one is new.
+//! Run with `cargo bench`.
+
+extern crate bml;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use bml::symtable::SymTable;
+
+const SHARED_NAMES: usize = 10_000;
+const LOOKUPS_PER_THREAD: usize = 1_000_000;
+const NEW_NAMES_PER_THREAD: usize = 100_000;
+
+/// Runs `work` on each of the given number of threads at once, giving the total rate of
+/// `sym_for()` calls per second
+fn measure<F>(threads: usize, calls_per_thread: usize, work: F) -> f64
+    where F: Fn(&SymTable, usize) + Send + Sync + 'static
+{
+    let st = SymTable::new();
+    let work = Arc::new(work);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads).map(|t| {
+        let st = st.clone();
+        let work = work.clone();
+        thread::spawn(move || work(&st, t) )
+    }).collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    (threads * calls_per_thread) as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let shared: Arc<Vec<String>> = Arc::new((0..SHARED_NAMES).map(|i| format!("symbol-{}", i) ).collect());
+    println!("{:>8} {:>22} {:>22}", "threads", "existing (M calls/s)", "new (M calls/s)");
+    for &threads in &[1, 2, 4, 8] {
+        let names = shared.clone();
+        let existing = measure(threads, LOOKUPS_PER_THREAD, move |st, t| {
+            // threads start at different points, so that they race to create each symbol
+            for i in 0..LOOKUPS_PER_THREAD {
+                st.sym_for(&names[(i + t * 7919) % names.len()]);
+            }
+        });
+        let fresh: Vec<Vec<String>> = (0..threads).map(|t| {
+            (0..NEW_NAMES_PER_THREAD).map(|i| format!("t{}-{}", t, i) ).collect()
+        }).collect();
+        let new = measure(threads, NEW_NAMES_PER_THREAD, move |st, t| {
+            for name in &fresh[t] {
+                st.sym_for(name);
+            }
+        });
+        println!("{:>8} {:>22.2} {:>22.2}", threads, existing / 1e6, new / 1e6);
+    }
+}
@@ -0,0 +1,338 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// An arbitrary-precision integer, stored as a sign and a magnitude of base-2^32 digits
+#[derive(Clone,PartialEq,Eq)]
+pub struct BigInt {
+    negative: bool,
+    /// least significant digit first, with no high zero digits (so zero is empty, and never
+    /// negative)
+    mag: Vec<u32>,
+}
+
+const BASE: u64 = 1 << 32;
+/// The largest power of ten which fits in one digit, used when converting to and from decimal
+const DEC_CHUNK: u32 = 1_000_000_000;
+const DEC_CHUNK_DIGITS: usize = 9;
+
+fn trim(mut mag: Vec<u32>) -> Vec<u32> {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    mag
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()) )
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, d) in long.iter().enumerate() {
+        let sum = u64::from(*d) + u64::from(*short.get(i).unwrap_or(&0)) + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+/// requires a >= b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, d) in a.iter().enumerate() {
+        let mut diff = i64::from(*d) - i64::from(*b.get(i).unwrap_or(&0)) - borrow;
+        if diff < 0 {
+            diff += BASE as i64;
+            borrow = 1;
+        } else {
+            borrow = 0;
+        }
+        result.push(diff as u32);
+    }
+    trim(result)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let t = u64::from(*x) * u64::from(*y) + u64::from(result[i + j]) + carry;
+            result[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    trim(result)
+}
+
+/// `mag * m + a`, in place
+fn mul_small_add(mag: &mut Vec<u32>, m: u32, a: u32) {
+    let mut carry = u64::from(a);
+    for d in mag.iter_mut() {
+        let t = u64::from(*d) * u64::from(m) + carry;
+        *d = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        mag.push(carry as u32);
+    }
+}
+
+/// Divides in place, returning the remainder
+fn divrem_small(mag: &mut Vec<u32>, d: u32) -> u32 {
+    let mut rem = 0u64;
+    for digit in mag.iter_mut().rev() {
+        let t = (rem << 32) | u64::from(*digit);
+        *digit = (t / u64::from(d)) as u32;
+        rem = t % u64::from(d);
+    }
+    let trimmed = trim(::std::mem::take(mag));
+    *mag = trimmed;
+    rem as u32
+}
+
+/// Binary long division, requiring a non-zero divisor
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if b.len() == 1 {
+        let mut q = a.to_vec();
+        let r = divrem_small(&mut q, b[0]);
+        return (q, trim(vec![r]));
+    }
+    if cmp_mag(a, b) == Ordering::Less {
+        return (vec![], a.to_vec());
+    }
+    let mut q = vec![0u32; a.len()];
+    let mut r: Vec<u32> = vec![];
+    for i in (0..a.len() * 32).rev() {
+        // r = r << 1 | bit i of a
+        mul_small_add(&mut r, 2, (a[i / 32] >> (i % 32)) & 1);
+        if cmp_mag(&r, b) != Ordering::Less {
+            r = sub_mag(&r, b);
+            q[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (trim(q), r)
+}
+
+impl BigInt {
+    fn new(negative: bool, mag: Vec<u32>) -> BigInt {
+        let mag = trim(mag);
+        BigInt {
+            negative: negative && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    pub fn from_i64(i: i64) -> BigInt {
+        let u = i.unsigned_abs();
+        BigInt::new(i < 0, vec![u as u32, (u >> 32) as u32])
+    }
+
+    /// `None` if the value is out of range
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let u = self.mag.iter().rev().fold(0u64, |acc, d| (acc << 32) | u64::from(*d) );
+        if self.negative {
+            if u <= i64::MIN.unsigned_abs() {
+                Some((u as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else if u <= i64::MAX as u64 {
+            Some(u as i64)
+        } else {
+            None
+        }
+    }
+
+    /// The nearest `f64`, or an infinity if the value is too large
+    pub fn to_f64(&self) -> f64 {
+        let f = self.mag.iter().rev().fold(0f64, |acc, d| acc * BASE as f64 + f64::from(*d) );
+        if self.negative { -f } else { f }
+    }
+
+    /// `(f, e)` such that the value is close to `f * 2^e`.  Only the leading digits go into `f`,
+    /// so unlike `to_f64` this stays finite however large the value is.
+    pub fn to_f64_exp(&self) -> (f64, i32) {
+        let skip = self.mag.len().saturating_sub(3);
+        let f = self.mag[skip..].iter().rev().fold(0f64, |acc, d| acc * BASE as f64 + f64::from(*d) );
+        (if self.negative { -f } else { f }, (skip * 32) as i32)
+    }
+
+    /// Parses an optionally signed string of decimal digits
+    pub fn parse(text: &str) -> Option<BigInt> {
+        let (negative, digits) = match text.as_bytes().first() {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit() ) {
+            return None;
+        }
+        let mut mag = vec![];
+        for chunk in digits.as_bytes().chunks(DEC_CHUNK_DIGITS) {
+            let val = chunk.iter().fold(0u32, |acc, b| acc * 10 + u32::from(b - b'0') );
+            mul_small_add(&mut mag, 10u32.pow(chunk.len() as u32), val);
+        }
+        Some(BigInt::new(negative, mag))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::new(false, self.mag.clone())
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::new(!self.negative, self.mag.clone())
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::new(other.negative, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::new(self.negative, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        BigInt::new(self.negative != other.negative, mul_mag(&self.mag, &other.mag))
+    }
+
+    /// Truncating division, giving the quotient and a remainder with the sign of `self`.
+    /// `None` if `other` is zero.
+    pub fn divrem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = divrem_mag(&self.mag, &other.mag);
+        Some((BigInt::new(self.negative != other.negative, q), BigInt::new(self.negative, r)))
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        let mut mag = self.mag.clone();
+        let mut chunks = vec![];
+        while !mag.is_empty() {
+            chunks.push(divrem_small(&mut mag, DEC_CHUNK));
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for c in chunks {
+            write!(f, "{:09}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BigInt({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BigInt;
+
+    fn big(s: &str) -> BigInt {
+        BigInt::parse(s).unwrap()
+    }
+
+    #[test]
+    fn decimal_round_trip() {
+        for s in &["0", "1", "-1", "4294967296", "-18446744073709551616", "1000000000", "123456789012345678901234567890"] {
+            assert_eq!(*s, big(s).to_string());
+        }
+        assert_eq!("0", big("-0").to_string());
+        assert_eq!("7", big("+0007").to_string());
+    }
+
+    #[test]
+    fn scaled_float() {
+        assert_eq!((-5.0, 0), big("-5").to_f64_exp());
+        let (f, e) = big("340282366920938463463374607431768211456").to_f64_exp();  // 2^128
+        assert_eq!((2f64.powi(64), 64), (f, e));
+    }
+
+    #[test]
+    fn i64_conversion() {
+        assert_eq!(Some(i64::MIN), BigInt::from_i64(i64::MIN).to_i64());
+        assert_eq!(Some(i64::MAX), BigInt::from_i64(i64::MAX).to_i64());
+        assert_eq!(None, big("9223372036854775808").to_i64());
+        assert_eq!(None, big("-9223372036854775809").to_i64());
+        assert_eq!(Some(-5), big("-5").to_i64());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(big("18446744073709551616"), big("18446744073709551615").add(&big("1")));
+        assert_eq!(big("-1"), big("18446744073709551615").sub(&big("18446744073709551616")));
+        assert_eq!(big("0"), big("-12345678901234567890").add(&big("12345678901234567890")));
+        assert_eq!(big("-121932631137021795223746380111126352690"), big("12345678901234567890").mul(&big("-9876543210987654321")));
+    }
+
+    #[test]
+    fn division() {
+        let (q, r) = big("121932631137021795223746380111126352691").divrem(&big("12345678901234567890")).unwrap();
+        assert_eq!(big("9876543210987654321"), q);
+        assert_eq!(big("1"), r);
+        let (q, r) = big("-7").divrem(&big("2")).unwrap();
+        assert_eq!((big("-3"), big("-1")), (q, r));
+        let (q, r) = big("100000000000000000000").divrem(&big("-3")).unwrap();
+        assert_eq!((big("-33333333333333333333"), big("1")), (q, r));
+        assert!(big("1").divrem(&big("0")).is_none());
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(big("-100000000000000000000") < big("-1"));
+        assert!(big("-1") < big("0"));
+        assert!(big("18446744073709551616") > big("18446744073709551615"));
+    }
+}
@@ -1,111 +1,180 @@
 use interpret::Interpreter;
 use interpret::Data;
+use interpret::EvalError;
+use interpret::ErrorKind;
 use symtable::SymTable;
 use parse::SExp;
+use number::Number;
+use print::Render;
+use std::cmp::Ordering;
+
+fn check_arity(name: &str, args: &[Data], expected: usize) -> Result<(), EvalError> {
+    if args.len() != expected {
+        Err(EvalError::new(ErrorKind::ArityError, format!("'{}' expected {} arguments, got {}", name, expected, args.len())))
+    } else {
+        Ok(())
+    }
+}
+
+/// The part of a number given by `part`, which only exact numbers have
+fn exact_part(name: &str, n: Number, part: fn(&Number) -> Option<Number>) -> Result<Number, EvalError> {
+    part(&n).ok_or_else(|| {
+        EvalError::with_value(ErrorKind::TypeError, Data::DExp(SExp::Num(n, None)), format!("invalid arguments for '{}', expected an exact number", name))
+    })
+}
+
+fn list_arg<'a>(name: &str, arg: &'a Data) -> Result<&'a [Data], EvalError> {
+    match *arg {
+        Data::DList(ref l) => Ok(l),
+        Data::DExp(SExp::Nil) => Ok(&[]),
+        _ => Err(EvalError::with_value(ErrorKind::TypeError, arg.clone(), format!("invalid arguments for '{}', expected a list", name)))
+    }
+}
+
+/// Checks for at least `min` arguments
+fn check_min_arity(name: &str, args: &[Data], min: usize) -> Result<(), EvalError> {
+    if args.len() < min {
+        Err(EvalError::new(ErrorKind::ArityError, format!("'{}' expected at least {} arguments, got {}", name, min, args.len())))
+    } else {
+        Ok(())
+    }
+}
+
+/// Calls `f` on successive elements of the lists, stopping at the end of the shortest
+fn map_lists(name: &str, interpreter: &Interpreter, f: &Data, lists: &[Data]) -> Result<Vec<Data>, EvalError> {
+    let lists = lists.iter().map(|l| list_arg(name, l) ).collect::<Result<Vec<&[Data]>, EvalError>>()?;
+    let len = lists.iter().map(|l| l.len() ).min().unwrap_or(0);
+    (0..len).map(|i| interpreter.apply_fun(f, lists.iter().map(|l| l[i].clone() ).collect()) ).collect()
+}
+
+/// Calls the predicate `f` with the given arguments, requiring a boolean result
+fn test(name: &str, interpreter: &Interpreter, f: &Data, args: Vec<Data>) -> Result<bool, EvalError> {
+    match interpreter.apply_fun(f, args)? {
+        Data::DExp(SExp::Boolean(b, _)) => Ok(b),
+        v => Err(EvalError::with_value(ErrorKind::TypeError, v, format!("the function passed to '{}' must return a boolean", name)))
+    }
+}
+
+/// A stable merge sort.  `sort_by()` won't do, since the comparison may fail, and (being user
+/// code) need not be a consistent ordering.
+fn merge_sort<F: Fn(&Data, &Data) -> Result<bool, EvalError>>(mut items: Vec<Data>, less: &F) -> Result<Vec<Data>, EvalError> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, less)?;
+    let right = merge_sort(right, less)?;
+    let mut result = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // taking from the left unless the right is strictly less keeps equal items in order
+        if less(r, l)? {
+            result.push(right.next().unwrap());
+        } else {
+            result.push(left.next().unwrap());
+        }
+    }
+    result.extend(left);
+    result.extend(right);
+    Ok(result)
+}
 
 pub fn init(st: &SymTable, interpreter: &Interpreter) {
     let println_sym = st.sym_for("println");
-    interpreter.define_native(println_sym, |args:&[Data]| {
-        println!("println: {:?}", args);
-        Data::DExp(SExp::Nil)
+    interpreter.define_native(println_sym, |interpreter, args:&[Data]| {
+        let text: Vec<String> = args.iter().map(|a| a.display(interpreter.symbols()).to_string() ).collect();
+        println!("{}", text.join(" "));
+        Ok(Data::DExp(SExp::Nil))
     });
 
-    let plus_sym = st.sym_for("plus");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 2 {
-            panic!("'plus' expected 2 arguments, {:?}", args)
-        }
-        match (&args[0], &args[1]) {
-            (&Data::DExp(SExp::Num(a)), &Data::DExp(SExp::Num(b))) => Data::DExp(SExp::Num(a+b)),
-            _ => panic!("invalid arguments for 'plus'")
-        }
-    });
+    for name in &["+", "plus"] {
+        interpreter.register(name, |a: Number, b: Number| a.add(&b) );
+    }
 
-    let plus_sym = st.sym_for("minus");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 2 {
-            panic!("'minus' expected 2 arguments, {:?}", args)
-        }
-        match (&args[0], &args[1]) {
-            (&Data::DExp(SExp::Num(a)), &Data::DExp(SExp::Num(b))) => Data::DExp(SExp::Num(a-b)),
-            _ => panic!("invalid arguments for 'minus'")
-        }
-    });
+    for name in &["-", "minus"] {
+        interpreter.register(name, |a: Number, b: Number| a.sub(&b) );
+    }
 
-    let plus_sym = st.sym_for("mul");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 2 {
-            panic!("'mul' expected 2 arguments, {:?}", args)
-        }
-        match (&args[0], &args[1]) {
-            (&Data::DExp(SExp::Num(a)), &Data::DExp(SExp::Num(b))) => Data::DExp(SExp::Num(a*b)),
-            _ => panic!("invalid arguments for 'mul'")
-        }
-    });
+    for name in &["*", "mul"] {
+        interpreter.register(name, |a: Number, b: Number| a.mul(&b) );
+    }
 
-    let plus_sym = st.sym_for("div");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 2 {
-            panic!("'div' expected 2 arguments, {:?}", args)
-        }
-        match (&args[0], &args[1]) {
-            (&Data::DExp(SExp::Num(a)), &Data::DExp(SExp::Num(b))) => Data::DExp(SExp::Num(a/b)),
-            _ => panic!("invalid arguments for 'div'")
-        }
-    });
+    for name in &["/", "div"] {
+        interpreter.register(name, |a: Number, b: Number| {
+            a.div(&b).ok_or_else(|| EvalError::new(ErrorKind::DivideByZero, "'/' by zero".to_string()) )
+        });
+    }
 
-    let plus_sym = st.sym_for("lt");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 2 {
-            panic!("'lt' expected 2 arguments, {:?}", args)
-        }
-        match (&args[0], &args[1]) {
-            (&Data::DExp(SExp::Num(a)), &Data::DExp(SExp::Num(b))) => Data::DExp(SExp::Boolean(a<b)),
-            _ => panic!("invalid arguments for 'lt'")
-        }
+    interpreter.register("=", |a: Number, b: Number| a.compare(&b) == Some(Ordering::Equal) );
+
+    for name in &["<", "lt"] {
+        interpreter.register(name, |a: Number, b: Number| a.compare(&b) == Some(Ordering::Less) );
+    }
+
+    for name in &["<=", "le"] {
+        interpreter.register(name, |a: Number, b: Number| matches!(a.compare(&b), Some(Ordering::Less) | Some(Ordering::Equal)) );
+    }
+
+    for name in &[">", "gt"] {
+        interpreter.register(name, |a: Number, b: Number| a.compare(&b) == Some(Ordering::Greater) );
+    }
+
+    for name in &[">=", "ge"] {
+        interpreter.register(name, |a: Number, b: Number| matches!(a.compare(&b), Some(Ordering::Greater) | Some(Ordering::Equal)) );
+    }
+
+    interpreter.register("numerator", |n: Number| exact_part("numerator", n, Number::numerator) );
+    interpreter.register("denominator", |n: Number| exact_part("denominator", n, Number::denominator) );
+    interpreter.register("exact->inexact", |n: Number| n.to_inexact() );
+    interpreter.register("floor", |n: Number| n.floor() );
+    interpreter.register("round", |n: Number| n.round() );
+    interpreter.register("truncate", |n: Number| n.truncate() );
+
+    let symbols = st.clone();
+    interpreter.register("gensym", move |prefix: Option<&str>| symbols.gensym(prefix.unwrap_or("g")) );
+
+    let symbols = st.clone();
+    interpreter.register("string->uninterned-symbol", move |name: &str| symbols.uninterned(name) );
+
+    interpreter.register("eqv?", |a: Data, b: Data| a.eqv(&b) );
+
+    interpreter.define_native(st.sym_for("map"), |interpreter, args:&[Data]| {
+        check_min_arity("map", args, 2)?;
+        Ok(Data::DList(map_lists("map", interpreter, &args[0], &args[1..])?))
     });
 
-    let plus_sym = st.sym_for("le");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 2 {
-            panic!("'le' expected 2 arguments, {:?}", args)
-        }
-        match (&args[0], &args[1]) {
-            (&Data::DExp(SExp::Num(a)), &Data::DExp(SExp::Num(b))) => Data::DExp(SExp::Boolean(a<=b)),
-            _ => panic!("invalid arguments for 'le'")
-        }
+    interpreter.define_native(st.sym_for("for-each"), |interpreter, args:&[Data]| {
+        check_min_arity("for-each", args, 2)?;
+        map_lists("for-each", interpreter, &args[0], &args[1..])?;
+        Ok(Data::DExp(SExp::Nil))
     });
 
-    let plus_sym = st.sym_for("gt");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 2 {
-            panic!("'gt' expected 2 arguments, {:?}", args)
-        }
-        match (&args[0], &args[1]) {
-            (&Data::DExp(SExp::Num(a)), &Data::DExp(SExp::Num(b))) => Data::DExp(SExp::Boolean(a>b)),
-            _ => panic!("invalid arguments for 'gt'")
-        }
+    interpreter.define_native(st.sym_for("filter"), |interpreter, args:&[Data]| {
+        check_arity("filter", args, 2)?;
+        let mut result = vec!();
+        for item in list_arg("filter", &args[1])? {
+            if test("filter", interpreter, &args[0], vec!(item.clone()))? {
+                result.push(item.clone());
+            }
+        }
+        Ok(Data::DList(result))
     });
 
-    let plus_sym = st.sym_for("ge");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 2 {
-            panic!("'ge' expected 2 arguments, {:?}", args)
-        }
-        match (&args[0], &args[1]) {
-            (&Data::DExp(SExp::Num(a)), &Data::DExp(SExp::Num(b))) => Data::DExp(SExp::Boolean(a>=b)),
-            _ => panic!("invalid arguments for 'ge'")
-        }
+    interpreter.define_native(st.sym_for("sort"), |interpreter, args:&[Data]| {
+        check_arity("sort", args, 2)?;
+        let items = list_arg("sort", &args[0])?.to_vec();
+        let sorted = merge_sort(items, &|a: &Data, b: &Data| test("sort", interpreter, &args[1], vec!(a.clone(), b.clone())) )?;
+        Ok(Data::DList(sorted))
     });
 
-    let plus_sym = st.sym_for("not");
-    interpreter.define_native(plus_sym, |args:&[Data]| {
-        if args.len() < 1 {
-            panic!("'not' expected 1 argument")
-        }
-        match &args[0] {
-            &Data::DExp(SExp::Boolean(a)) => Data::DExp(SExp::Boolean(!a)),
-            _ => panic!("invalid arguments for 'not'")
-        }
+    interpreter.define_native(st.sym_for("apply"), |interpreter, args:&[Data]| {
+        check_min_arity("apply", args, 2)?;
+        let (last, init) = args[1..].split_last().unwrap();
+        let mut fun_args = init.to_vec();
+        fun_args.extend_from_slice(list_arg("apply", last)?);
+        interpreter.apply_fun(&args[0], fun_args)
     });
+
+    interpreter.register("not", |a: bool| !a );
 }
@@ -1,23 +1,89 @@
+use std::cell::Cell;
 use std::cell::RefCell;
 use std::fmt;
 use parse::SExp;
-use symtable::SymbolRef;
+use parse::Span;
+use symtable::Symbol;
+use symtable::SymbolMap;
 use symtable::SymTable;
-use std::collections::HashMap;
+use native::NativeFn;
+use print::Render;
 use std::slice::Iter;
 use std::rc::Rc;
 
+/// Given the interpreter making the call, so that functions passed as arguments can be called
+/// back with `Interpreter::apply_fun()`
+type NativeCode = Rc<Box<dyn Fn(&Interpreter, &[Data])->EvalResult>>;
+
 #[derive(Clone)]
-enum Fun {
-    Native{name:SymbolRef, code:Rc<Box<Fn(&[Data])->Data>>},
-    User{params: Vec<SymbolRef>, body: SExp}
+pub enum Fun {
+    Native{name:Symbol, code:NativeCode},
+    User(Rc<Lambda>)
+}
+
+/// A function created by evaluating a `lambda` or `case-lambda` form, closing over the
+/// environment it was created in
+pub struct Lambda {
+    /// The variable the function was first bound to, if any, for use when printing it
+    name: Option<Symbol>,
+    /// A call runs the first clause which accepts the number of arguments given.  There is just
+    /// one unless the function came from `case-lambda`.
+    clauses: Vec<Clause>,
+    env: Rc<Env>,
+}
+
+#[derive(PartialEq)]
+struct Clause {
+    params: Params,
+    /// evaluated in sequence, giving the value of the last
+    body: Vec<SExp>,
+}
+
+/// The params of a function, as written `(a b #!optional (c 1) #!rest r #!key (d 2))`, where
+/// each section is optional, or `(a b . r)` when there is a rest param but no others
+#[derive(PartialEq,Default)]
+struct Params {
+    required: Vec<Symbol>,
+    /// with the expression giving the default value, if there is one
+    optional: Vec<(Symbol, Option<SExp>)>,
+    /// bound to a list of any arguments left over after the required and optional ones
+    rest: Option<Symbol>,
+    /// passed as `name: value` after the positional arguments, in any order, so each is given
+    /// with its keyword `name:` as well as its default
+    keys: Vec<(Symbol, Symbol, Option<SExp>)>,
+}
+
+impl Params {
+    fn accepts(&self, n: usize) -> bool {
+        n >= self.required.len() && (self.rest.is_some() || !self.keys.is_empty() || n <= self.required.len() + self.optional.len())
+    }
+}
+
+impl Lambda {
+    /// How the function is referred to in error messages
+    fn describe(&self, st: &SymTable) -> String {
+        match self.name {
+            Some(ref name) => format!("'{}'", name.write(st)),
+            None => "function".to_string(),
+        }
+    }
 }
 
 impl fmt::Debug for Fun {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &Fun::Native{ref name, ..} => write!(f, "Fun::Native {{ name: {:?}, code: .. }}", name),
-            &Fun::User{..} => write!(f, "Fun::User {{ .. }}"),
+        match *self {
+            Fun::Native{ref name, ..} => write!(f, "Fun::Native {{ name: {:?}, code: .. }}", name),
+            Fun::User(..) => write!(f, "Fun::User {{ .. }}"),
+        }
+    }
+}
+
+impl Fun {
+    /// The name the function was defined with, if it has one
+    pub fn name(&self) -> Option<Symbol> {
+        match *self {
+            Fun::Native{name, ..} => Some(name),
+            Fun::User(ref lambda) => lambda.name,
         }
     }
 }
@@ -25,13 +91,11 @@ impl fmt::Debug for Fun {
 impl PartialEq for Fun {
     fn eq(&self, other: &Fun) -> bool {
         match (self, other) {
-            (&Fun::Native{ref name, ref code}, &Fun::Native{name:ref othername, code:ref othercode}) => {
-                let a = code.as_ref() as *const _;
-                let b = othercode.as_ref() as *const _;
-                a == b
+            (Fun::Native{code, ..}, Fun::Native{code:othercode, ..}) => {
+                Rc::ptr_eq(code, othercode)
             },
-            (&Fun::User{ref params, ref body}, &Fun::User{params:ref otherparams, body:ref otherbody}) => {
-                params == otherparams && body == otherbody
+            (Fun::User(a), Fun::User(b)) => {
+                a.clauses == b.clauses && Rc::ptr_eq(&a.env, &b.env)
             },
             _ => false,
         }
@@ -40,208 +104,1018 @@ impl PartialEq for Fun {
 impl Eq for Fun {
 }
 
-impl Fun {
-    fn apply(&self, interp:&Interpreter, args: &[Data]) -> Data {
+#[derive(Debug,Eq,PartialEq,Clone)]
+#[allow(clippy::enum_variant_names)]
+pub enum Data {
+    /// Any kind of atom.  Lists are represented by `DList` rather than `SExp::List`, so that
+    /// they can hold any kind of value.
+    DExp(SExp),
+    DFun(Fun),
+    DList(Vec<Data>),
+}
+
+impl Data {
+    fn is_empty_list(&self) -> bool {
         match *self {
-            Fun::Native{ref name, ref code} => (code)(args),
-            Fun::User{ref params, ref body} => interp.user_call(args, params, body),
+            Data::DList(ref l) => l.is_empty(),
+            Data::DExp(SExp::Nil) => true,
+            _ => false,
         }
     }
+
+    /// Equivalence in the sense of Scheme's `eqv?`.  Values here have no identity beyond their
+    /// contents, so atoms (including strings) are compared by value, with numbers only equivalent
+    /// if both are exact or both inexact.  Functions are compared as for `==`, and the only lists
+    /// which are equivalent are empty ones.
+    pub fn eqv(&self, other: &Data) -> bool {
+        match (self, other) {
+            (Data::DList(_), _) | (_, Data::DList(_)) | (Data::DExp(SExp::Nil), _) | (_, Data::DExp(SExp::Nil)) =>
+                self.is_empty_list() && other.is_empty_list(),
+            _ => self == other,
+        }
+    }
+
+    /// The value of the given expression taken literally, as by `quote`
+    pub fn from_sexp(s: &SExp) -> Data {
+        match *s {
+            SExp::List(ref l, _) => Data::DList(l.iter().map(Data::from_sexp).collect()),
+            ref atom => Data::DExp(atom.clone()),
+        }
+    }
+}
+
+#[derive(Debug,Eq,PartialEq,Clone,Copy)]
+pub enum ErrorKind {
+    /// A symbol was evaluated which has no binding in any enclosing environment
+    UnboundVariable,
+    /// Something other than a function appeared in operator position
+    NotCallable,
+    /// A value of the wrong type was supplied
+    TypeError,
+    /// A function was called with the wrong number of arguments
+    ArityError,
+    DivideByZero,
+    /// A special form was not written the right way
+    SyntaxError,
+    /// Calls or expressions were nested more deeply than the interpreter's recursion limit
+    RecursionLimit,
 }
 
 #[derive(Debug,Eq,PartialEq,Clone)]
-pub enum Data {
-    DExp(SExp),
-    DFun(Fun),
+pub struct EvalError {
+    pub kind: ErrorKind,
+    /// The value which caused the problem, if there is a single culprit
+    pub value: Option<Box<Data>>,
+    pub msg: String,
+    /// The source of the innermost form being evaluated when the error occurred
+    pub span: Option<Span>,
 }
 
-struct Env {
+impl EvalError {
+    pub fn new(kind: ErrorKind, msg: String) -> EvalError {
+        EvalError {
+            kind,
+            value: None,
+            msg,
+            span: None,
+        }
+    }
+
+    pub fn with_value(kind: ErrorKind, value: Data, msg: String) -> EvalError {
+        EvalError {
+            kind,
+            value: Some(Box::new(value)),
+            msg,
+            span: None,
+        }
+    }
+
+    /// Records the given location, unless a more specific one is already known
+    fn at(mut self, span: Option<Span>) -> EvalError {
+        if self.span.is_none() {
+            self.span = span;
+        }
+        self
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
+        write!(f, "{:?}: {}", self.kind, self.msg)
+    }
+}
+
+pub type EvalResult = Result<Data, EvalError>;
+
+/// The result of evaluating an expression in tail position.  Rather than growing the Rust stack
+/// by making a call from tail position, the evaluator hands the call back to be made by a loop
+/// further up (see `Interpreter::call()`).
+enum Tail {
+    Value(Data),
+    /// The function to call, its arguments, and the location of the call
+    Call(Fun, Vec<Data>, Option<Span>),
+}
+
+type TailResult = Result<Tail, EvalError>;
+
+fn syntax_error<T>(msg: &str) -> Result<T, EvalError> {
+    Err(EvalError::new(ErrorKind::SyntaxError, msg.to_string()))
+}
+
+pub struct Env {
     parent: Option<Rc<Env>>,  // TODO: try removing Rc in favor of &, and a mess of explicit lifetime annotations
-    vars: RefCell<HashMap<SymbolRef, Data>>,
+    /// `None` for a variable which is bound but not yet assigned a value, as are those of
+    /// `letrec` while their inits are evaluated
+    vars: RefCell<SymbolMap<Option<Data>>>,
 }
 
 impl Env {
     fn new(parent: Option<Rc<Env>>) -> Env {
         Env {
-            parent: parent,
-            vars: RefCell::new(HashMap::new()),
+            parent,
+            vars: RefCell::new(SymbolMap::default()),
         }
     }
+
+    fn define(&self, name: Symbol, val: Data) {
+        self.vars.borrow_mut().insert(name, Some(val));
+    }
 }
 
+/// Enough for fairly deep recursion, while using no more than about half of the 8MB stack which
+/// a main thread gets by default.  Unoptimised builds use several times as much stack per level.
+pub const DEFAULT_RECURSION_LIMIT: usize = if cfg!(debug_assertions) { 500 } else { 2000 };
+
 pub struct Interpreter {
-    env: RefCell<Rc<Env>>,
-    sym_if: SymbolRef,
-    sym_lambda: SymbolRef,
-    sym_let: SymbolRef,
+    env: Rc<Env>,
+    st: SymTable,
+    /// How many evaluations and calls are in progress, each of which uses native stack
+    depth: Cell<usize>,
+    recursion_limit: Cell<usize>,
+    sym_if: Symbol,
+    sym_lambda: Symbol,
+    sym_case_lambda: Symbol,
+    sym_optional: Symbol,
+    sym_rest: Symbol,
+    sym_key: Symbol,
+    sym_dot: Symbol,
+    sym_define: Symbol,
+    sym_set: Symbol,
+    sym_let: Symbol,
+    sym_let_star: Symbol,
+    sym_letrec: Symbol,
+    sym_letrec_star: Symbol,
+    sym_begin: Symbol,
+    sym_and: Symbol,
+    sym_or: Symbol,
+    sym_when: Symbol,
+    sym_unless: Symbol,
+    sym_cond: Symbol,
+    sym_case: Symbol,
+    sym_else: Symbol,
+    sym_arrow: Symbol,
+    sym_quote: Symbol,
+    sym_quasiquote: Symbol,
+    sym_unquote: Symbol,
+    sym_unquote_splicing: Symbol,
 }
 
 impl Interpreter {
     pub fn new(st: &SymTable) -> Interpreter {
         Interpreter {
-            env: RefCell::new(Rc::new(Env::new(None))),
+            env: Rc::new(Env::new(None)),
+            st: st.clone(),
+            depth: Cell::new(0),
+            recursion_limit: Cell::new(DEFAULT_RECURSION_LIMIT),
             sym_if: st.sym_for("if"),
             sym_lambda: st.sym_for("lambda"),
+            sym_case_lambda: st.sym_for("case-lambda"),
+            sym_optional: st.sym_for("#!optional"),
+            sym_rest: st.sym_for("#!rest"),
+            sym_key: st.sym_for("#!key"),
+            sym_dot: st.sym_for("."),
+            sym_define: st.sym_for("define"),
+            sym_set: st.sym_for("set!"),
             sym_let: st.sym_for("let"),
+            sym_let_star: st.sym_for("let*"),
+            sym_letrec: st.sym_for("letrec"),
+            sym_letrec_star: st.sym_for("letrec*"),
+            sym_begin: st.sym_for("begin"),
+            sym_and: st.sym_for("and"),
+            sym_or: st.sym_for("or"),
+            sym_when: st.sym_for("when"),
+            sym_unless: st.sym_for("unless"),
+            sym_cond: st.sym_for("cond"),
+            sym_case: st.sym_for("case"),
+            sym_else: st.sym_for("else"),
+            sym_arrow: st.sym_for("=>"),
+            sym_quote: st.sym_for("quote"),
+            sym_quasiquote: st.sym_for("quasiquote"),
+            sym_unquote: st.sym_for("unquote"),
+            sym_unquote_splicing: st.sym_for("unquote-splicing"),
         }
     }
 
-    pub fn eval(&self, s:&Data) -> Data {
-        match s {
-            &Data::DExp(ref s) => self.eval_sexp(s),
-            _ => panic!("not able to evaluate {:?}", s)
+    /// The table of the symbols this interpreter evaluates, through which their names are found
+    pub fn symbols(&self) -> &SymTable {
+        &self.st
+    }
+
+    /// Sets how deeply evaluations and non-tail calls may nest before failing with
+    /// `ErrorKind::RecursionLimit`.  Each level uses native stack, so a host running the
+    /// interpreter on a thread with less stack than the main thread may need a lower limit.
+    pub fn set_recursion_limit(&self, limit: usize) {
+        self.recursion_limit.set(limit);
+    }
+
+    /// Runs `f` one level deeper, or fails if that would pass the recursion limit, so that deep
+    /// recursion in a program gives an error rather than overflowing the stack
+    fn nested<T, F: FnOnce() -> Result<T, EvalError>>(&self, f: F) -> Result<T, EvalError> {
+        let depth = self.depth.get();
+        if depth >= self.recursion_limit.get() {
+            return Err(EvalError::new(ErrorKind::RecursionLimit, format!("recursion limit of {} exceeded", self.recursion_limit.get())));
         }
+        self.depth.set(depth + 1);
+        let result = f();
+        self.depth.set(depth);
+        result
     }
 
-    pub fn eval_expressions(&self, l:&Vec<SExp>) -> Data {
-        l.iter().fold(Data::DExp(SExp::Nil), |_, x| self.eval_sexp(x))
+    pub fn eval(&self, s:&Data) -> EvalResult {
+        match *s {
+            Data::DExp(ref s) => self.eval_sexp(s),
+            _ => Err(EvalError::with_value(ErrorKind::TypeError, s.clone(), format!("not able to evaluate {}", s.write(&self.st))))
+        }
     }
 
-    pub fn eval_sexp(&self, s:&SExp) -> Data {
-        match s {
-            &SExp::List(ref l) => self.list(l),
-            &SExp::Num(ref n) => Data::DExp(SExp::Num(*n)),
-            &SExp::LString(ref s) => Data::DExp(SExp::LString(s.clone())),
-            &SExp::Boolean(ref b) => Data::DExp(SExp::Boolean(*b)),
-            &SExp::Sym(ref b) => self.lookup(b),
-            _ => panic!("not able to evaluate {:?}", s)
+    /// A symbol to be bound by `form`, which may be any symbol other than a keyword
+    fn variable(&self, s: Symbol, form: &str) -> Result<Symbol, EvalError> {
+        if self.st.is_keyword(s) {
+            return syntax_error(&format!("'{}' cannot bind the keyword {}", form, s.write(&self.st)));
+        }
+        Ok(s)
+    }
+
+    /// The keyword naming the argument for the key param `p`
+    fn keyword(&self, p: Symbol) -> Symbol {
+        self.st.sym_for(&format!("{}:", self.st.name(p)))
+    }
+
+    /// The function which `v` must be, for it to be called
+    fn callable(&self, v: Data) -> Result<Fun, EvalError> {
+        match v {
+            Data::DFun(f) => Ok(f),
+            v => Err(EvalError::with_value(ErrorKind::NotCallable, v.clone(), format!("not a function: {}", v.write(&self.st)))),
+        }
+    }
+
+    pub fn eval_expressions(&self, l:&[SExp]) -> EvalResult {
+        let mut result = Data::DExp(SExp::Nil);
+        for x in l {
+            result = self.eval_sexp(x)?;
         }
+        Ok(result)
+    }
+
+    /// Evaluates the given expression in the top-level environment
+    pub fn eval_sexp(&self, s:&SExp) -> EvalResult {
+        self.eval_in(s, &self.env)
     }
 
-    fn list(&self, l:&Vec<SExp>) -> Data {
+    fn eval_in(&self, s:&SExp, env:&Rc<Env>) -> EvalResult {
+        self.nested(|| match self.eval_tail(s, env)? {
+            Tail::Value(v) => Ok(v),
+            Tail::Call(f, args, span) => self.call(f, args, span),
+        })
+    }
+
+    /// Evaluates an expression in tail position, leaving any call it makes to the caller
+    fn eval_tail(&self, s:&SExp, env:&Rc<Env>) -> TailResult {
+        match *s {
+            SExp::List(ref l, span) => self.list(l, span, env).map_err(|e| e.at(span) ),
+            SExp::Num(ref n, _) => Ok(Tail::Value(Data::DExp(SExp::Num(n.clone(), None)))),
+            SExp::LString(ref s, _) => Ok(Tail::Value(Data::DExp(SExp::LString(s.clone(), None)))),
+            SExp::Boolean(b, _) => Ok(Tail::Value(Data::DExp(SExp::Boolean(b, None)))),
+            SExp::Sym(ref b, span) => self.lookup(b, env).map(Tail::Value).map_err(|e| e.at(span) ),
+            SExp::Nil => Err(EvalError::with_value(ErrorKind::TypeError, Data::DExp(s.clone()), format!("not able to evaluate {}", s.write(&self.st))))
+        }
+    }
+
+    fn list(&self, l:&[SExp], span:Option<Span>, env:&Rc<Env>) -> TailResult {
         let mut args = l.iter();
         match args.next() {
-            None => panic!("tried to invoke empty list {:?}", l),
-            Some(&SExp::Sym(ref s)) => {
+            None => Err(EvalError::with_value(ErrorKind::NotCallable, Data::DExp(SExp::List(vec!(), None)), "tried to invoke empty list".to_string())),
+            Some(SExp::Sym(s, _)) => {
                 if s == &self.sym_if {
-                    self.form_if(args)
+                    self.form_if(args, env)
                 } else if s == &self.sym_lambda {
-                    self.form_lambda(args)
+                    self.form_lambda(args, env).map(Tail::Value)
+                } else if s == &self.sym_case_lambda {
+                    self.form_case_lambda(args, env).map(Tail::Value)
+                } else if s == &self.sym_define {
+                    self.form_define(args, env).map(Tail::Value)
+                } else if s == &self.sym_set {
+                    self.form_set(args, env).map(Tail::Value)
                 } else if s == &self.sym_let {
-                    self.form_let(args)
+                    self.form_let(args, span, env)
+                } else if s == &self.sym_let_star {
+                    self.form_let_star(args, env)
+                } else if s == &self.sym_letrec {
+                    self.form_letrec(args, env, false)
+                } else if s == &self.sym_letrec_star {
+                    self.form_letrec(args, env, true)
+                } else if s == &self.sym_begin {
+                    self.eval_body(args.as_slice(), env)
+                } else if s == &self.sym_and {
+                    self.form_and_or(args, env, false)
+                } else if s == &self.sym_or {
+                    self.form_and_or(args, env, true)
+                } else if s == &self.sym_when {
+                    self.form_when(args, env, true)
+                } else if s == &self.sym_unless {
+                    self.form_when(args, env, false)
+                } else if s == &self.sym_cond {
+                    self.form_cond(args, span, env)
+                } else if s == &self.sym_case {
+                    self.form_case(args, span, env)
+                } else if s == &self.sym_quote {
+                    self.form_quote(args).map(Tail::Value)
+                } else if s == &self.sym_quasiquote {
+                    self.form_quasiquote(args, env).map(Tail::Value)
+                } else if s == &self.sym_unquote || s == &self.sym_unquote_splicing {
+                    syntax_error(&format!("{} may only appear within 'quasiquote'", s.write(&self.st)))
                 } else {
-                    self.apply(s, args)
+                    self.apply(&l[0], args, span, env)
                 }
             },
-            Some(other @ _) => panic!("expected symbol, found {:?}", other)
+            Some(op) => self.apply(op, args, span, env),
         }
     }
 
-    fn apply(&self, s: &SymbolRef, args: Iter<SExp>) -> Data {
-        let mut env = Some(self.env.borrow().clone());
-        while let Some(envref) = env {
-            match envref.vars.borrow().get(s) {
-                Some(&Data::DFun(ref f)) => {
-                    let vals = args.map(|a| self.eval_sexp(a) ).collect::<Vec<Data>>();
-                    return f.apply(&self, &vals);
-                },
-                Some(v) => panic!("not a function: {:?}", v),
-                None => {
-                    env = envref.parent.clone();
+    /// Calls the function given by the operator expression `op`, which may be any expression,
+    /// with the values of the argument expressions.  The operator is evaluated first, then the
+    /// arguments from left to right.
+    fn apply(&self, op: &SExp, args: Iter<SExp>, span:Option<Span>, env: &Rc<Env>) -> TailResult {
+        let f = self.callable(self.eval_in(op, env)?)?;
+        let vals = args.map(|a| self.eval_in(a, env) ).collect::<Result<Vec<Data>, EvalError>>()?;
+        Ok(Tail::Call(f, vals, span))
+    }
+
+    /// Calls the given function, and then keeps making any calls that it in turn makes from
+    /// tail position, so that tail-recursive loops run in constant stack space
+    fn call(&self, f: Fun, args: Vec<Data>, span: Option<Span>) -> EvalResult {
+        self.nested(|| {
+            let mut f = f;
+            let mut args = args;
+            let mut span = span;
+            loop {
+                let tail = match f {
+                    Fun::Native{ref code, ..} => return (code)(self, &args).map_err(|e| e.at(span) ),
+                    Fun::User(ref lambda) => {
+                        let clause = match lambda.clauses.iter().find(|c| c.params.accepts(args.len()) ) {
+                            Some(clause) => clause,
+                            // with a single clause, binding the args explains what was expected
+                            None if lambda.clauses.len() == 1 => &lambda.clauses[0],
+                            None => return Err(EvalError::new(ErrorKind::ArityError, format!("no clause of {} accepts {} arguments", lambda.describe(&self.st), args.len())).at(span)),
+                        };
+                        let env = self.bind_args(lambda, &clause.params, args).map_err(|e| e.at(span) )?;
+                        self.eval_body(&clause.body, &env)?
+                    },
+                };
+                match tail {
+                    Tail::Value(v) => return Ok(v),
+                    Tail::Call(next_f, next_args, next_span) => {
+                        f = next_f;
+                        args = next_args;
+                        span = next_span;
+                    }
                 }
             }
-        };
-        panic!("undefined {:?}", s);
+        })
     }
 
-    fn lookup(&self, s: &SymbolRef) -> Data {
-        let mut env = Some(self.env.borrow().clone());
+    /// The value bound to `s`.  Keywords can never be bound, so they are only checked for once
+    /// no binding has been found, keeping the symbol table out of ordinary variable lookups.
+    fn lookup(&self, s: &Symbol, env: &Rc<Env>) -> EvalResult {
+        let mut env = Some(env.clone());
         while let Some(envref) = env {
             match envref.vars.borrow().get(s) {
-                Some(d) => {
-                    return (*d).clone();
+                Some(Some(d)) => {
+                    return Ok((*d).clone());
+                },
+                Some(None) => {
+                    return Err(EvalError::with_value(ErrorKind::UnboundVariable, Data::DExp(SExp::Sym(*s, None)), format!("{} used before it was assigned", s.write(&self.st))));
                 },
                 None => {
                     env = envref.parent.clone();
                 }
             }
         };
-        panic!("undefined {:?}", s);
+        if self.st.is_keyword(*s) {
+            return Ok(Data::DExp(SExp::Sym(*s, None)));
+        }
+        Err(EvalError::with_value(ErrorKind::UnboundVariable, Data::DExp(SExp::Sym(*s, None)), format!("undefined {}", s.write(&self.st))))
+    }
+
+    /// Evaluates a sequence of expressions, such as a function body, giving the value of the last
+    /// (or nil if there are none).  The last is in tail position.
+    fn eval_body(&self, body: &[SExp], env: &Rc<Env>) -> TailResult {
+        match body.split_last() {
+            None => Ok(Tail::Value(Data::DExp(SExp::Nil))),
+            Some((last, init)) => {
+                for e in init {
+                    self.eval_in(e, env)?;
+                }
+                self.eval_tail(last, env)
+            }
+        }
     }
 
-    fn form_if(&self, mut args: Iter<SExp>) -> Data {
+    /// Evaluates the test of a conditional form, which must give a boolean
+    fn condition(&self, e: &SExp, env: &Rc<Env>, form: &str) -> Result<bool, EvalError> {
+        match self.eval_in(e, env)? {
+            Data::DExp(SExp::Boolean(b, _)) => Ok(b),
+            v => Err(EvalError::with_value(ErrorKind::TypeError, v, format!("'{}' condition must be a boolean value", form)))
+        }
+    }
+
+    fn form_if(&self, mut args: Iter<SExp>, env: &Rc<Env>) -> TailResult {
         match args.next() {
-            None => panic!("missing condition expression in 'if'"),
+            None => syntax_error("missing condition expression in 'if'"),
             Some(e) => {
-                match self.eval_sexp(e) {
-                    Data::DExp(SExp::Boolean(true)) => {
-                        match args.next() {
-                            Some(a) => self.eval_sexp(a),
-                            None => panic!("too few values for 'if' expression"),
-                        }
-                    },
-                    Data::DExp(SExp::Boolean(false)) => {
-                        args.next();  // skip
-                        match args.next() {
-                            Some(a) => self.eval_sexp(a),
-                            None => Data::DExp(SExp::Nil),
-                        }
-                    },
-                    _ => panic!("'if' condition must be a boolean value")
+                if self.condition(e, env, "if")? {
+                    match args.next() {
+                        Some(a) => self.eval_tail(a, env),
+                        None => syntax_error("too few values for 'if' expression"),
+                    }
+                } else {
+                    args.next();  // skip
+                    match args.next() {
+                        Some(a) => self.eval_tail(a, env),
+                        None => Ok(Tail::Value(Data::DExp(SExp::Nil))),
+                    }
                 }
             }
         }
     }
 
-    fn form_lambda(&self, mut args: Iter<SExp>) -> Data {
-        let mut param_syms:Vec<SymbolRef> = vec!();
+    /// `when` if `run_if` is true, else `unless`
+    fn form_when(&self, mut args: Iter<SExp>, env: &Rc<Env>, run_if: bool) -> TailResult {
+        let form = if run_if { "when" } else { "unless" };
         match args.next() {
-            None => panic!("missing argment list and function body in 'lambda'"),
-            Some(&SExp::List(ref params)) => {
-                for p in params {
-                    match p {
-                        &SExp::Sym(ref s) => param_syms.push(s.clone()),
-                        e => panic!("'lambda' param list entries must be symbols: {:?}", e)
-                    }
+            None => syntax_error(&format!("missing condition expression in '{}'", form)),
+            Some(e) => {
+                if self.condition(e, env, form)? == run_if {
+                    self.eval_body(args.as_slice(), env)
+                } else {
+                    Ok(Tail::Value(Data::DExp(SExp::Nil)))
                 }
-                match args.next() {
-                    None => panic!("missing function body in 'lambda'"),
-                    Some(s) => {
-                        Data::DFun(Fun::User{params:param_syms, body: s.clone()})
+            }
+        }
+    }
+
+    /// `and` if `stop_at` is false, else `or`.  Operands are evaluated until one gives
+    /// `stop_at`, which is the result.  Otherwise the result is that of the last operand, which
+    /// is in tail position, and so (as in the 'then' and 'else' branches of `if`) need not be a
+    /// boolean.
+    fn form_and_or(&self, args: Iter<SExp>, env: &Rc<Env>, stop_at: bool) -> TailResult {
+        let form = if stop_at { "or" } else { "and" };
+        match args.as_slice().split_last() {
+            None => Ok(Tail::Value(Data::DExp(SExp::Boolean(!stop_at, None)))),
+            Some((last, init)) => {
+                for e in init {
+                    if self.condition(e, env, form)? == stop_at {
+                        return Ok(Tail::Value(Data::DExp(SExp::Boolean(stop_at, None))));
                     }
                 }
+                self.eval_tail(last, env)
+            }
+        }
+    }
+
+    /// The body of a `cond` or `case` clause which has been selected.  With `=>`, the body is a
+    /// single expression giving a function, which is called with `value`.
+    fn clause_body(&self, body: &[SExp], value: Data, span: Option<Span>, env: &Rc<Env>) -> TailResult {
+        match body.first() {
+            Some(SExp::Sym(s, _)) if *s == self.sym_arrow => {
+                match &body[1..] {
+                    [receiver] => Ok(Tail::Call(self.callable(self.eval_in(receiver, env)?)?, vec!(value), span)),
+                    _ => syntax_error("'=>' must be followed by exactly one expression"),
+                }
             },
-            Some(s) => panic!("'lambda' definition requires an argument list: {:?}", s)
+            _ => self.eval_body(body, env),
         }
     }
 
-    fn form_let(&self, mut args: Iter<SExp>) -> Data {
+    /// Each clause is `(test body...)`, `(test => receiver)` or, last of all, `(else body...)`.
+    /// A test must give a boolean, except in a `=>` clause, where any value other than `#f`
+    /// selects the clause and is passed to the receiver.  A clause with no body gives the value
+    /// of its test.
+    fn form_cond(&self, args: Iter<SExp>, span: Option<Span>, env: &Rc<Env>) -> TailResult {
+        let clauses = args.as_slice();
+        for (i, clause) in clauses.iter().enumerate() {
+            let (test, body) = match *clause {
+                SExp::List(ref l, _) if !l.is_empty() => (&l[0], &l[1..]),
+                ref c => return syntax_error(&format!("'cond' clause must be a non-empty list, got {}", c.write(&self.st))),
+            };
+            if *test == SExp::Sym(self.sym_else, None) {
+                if i != clauses.len() - 1 {
+                    return syntax_error("'else' must be the last clause of 'cond'");
+                }
+                return self.eval_body(body, env);
+            }
+            let selected = if body.first() == Some(&SExp::Sym(self.sym_arrow, None)) {
+                match self.eval_in(test, env)? {
+                    Data::DExp(SExp::Boolean(false, _)) => None,
+                    v => Some(v),
+                }
+            } else if self.condition(test, env, "cond")? {
+                Some(Data::DExp(SExp::Boolean(true, None)))
+            } else {
+                None
+            };
+            if let Some(value) = selected {
+                if body.is_empty() {
+                    return Ok(Tail::Value(value));
+                }
+                return self.clause_body(body, value, span, env);
+            }
+        }
+        Ok(Tail::Value(Data::DExp(SExp::Nil)))
+    }
+
+    /// `(case key ((datum...) body...) ... (else body...))`, selecting the first clause with a
+    /// datum which is `eqv?` to the value of `key`.  As with `cond`, a body may be `=> receiver`,
+    /// the receiver then being passed the key.
+    fn form_case(&self, mut args: Iter<SExp>, span: Option<Span>, env: &Rc<Env>) -> TailResult {
+        let key = match args.next() {
+            None => return syntax_error("missing key expression in 'case'"),
+            Some(e) => self.eval_in(e, env)?,
+        };
+        let clauses = args.as_slice();
+        for (i, clause) in clauses.iter().enumerate() {
+            let (data, body) = match *clause {
+                SExp::List(ref l, _) if l.len() >= 2 => (&l[0], &l[1..]),
+                ref c => return syntax_error(&format!("'case' clause must be a list of data and a body, got {}", c.write(&self.st))),
+            };
+            let selected = match *data {
+                SExp::Sym(s, _) if s == self.sym_else => {
+                    if i != clauses.len() - 1 {
+                        return syntax_error("'else' must be the last clause of 'case'");
+                    }
+                    true
+                },
+                SExp::List(ref l, _) => l.iter().any(|d| Data::from_sexp(d).eqv(&key) ),
+                ref d => return syntax_error(&format!("'case' clause must start with a list of data, got {}", d.write(&self.st))),
+            };
+            if selected {
+                return self.clause_body(body, key, span, env);
+            }
+        }
+        Ok(Tail::Value(Data::DExp(SExp::Nil)))
+    }
+
+    /// Creates a closure over `env`, the environment in effect where the `lambda` form appears
+    fn form_lambda(&self, mut args: Iter<SExp>, env: &Rc<Env>) -> EvalResult {
         match args.next() {
-            None => panic!("missing variable name in 'let'"),
-            Some(&SExp::Sym(ref name)) => {
-                match args.next() {
-                    None => panic!("missing variable value in 'let'"),
-                    Some(s) => {
-                        let val = self.eval_sexp(s);
-                        let env = self.env.borrow();
-                        env.vars.borrow_mut().insert(name.clone(), val);
-                        Data::DExp(SExp::Nil)
+            None => syntax_error("missing argment list and function body in 'lambda'"),
+            Some(formals) => {
+                let clause = self.clause(self.formals(formals, "lambda")?, args.as_slice(), "lambda")?;
+                Ok(Data::DFun(Fun::User(Rc::new(Lambda{name: None, clauses: vec!(clause), env: env.clone()}))))
+            },
+        }
+    }
+
+    /// `(case-lambda (formals body...) ...)` creates a function which, when called, runs the
+    /// first clause whose params accept the number of arguments given
+    fn form_case_lambda(&self, args: Iter<SExp>, env: &Rc<Env>) -> EvalResult {
+        let clauses = args.map(|c| {
+            match *c {
+                SExp::List(ref l, _) if !l.is_empty() => self.clause(self.formals(&l[0], "case-lambda")?, &l[1..], "case-lambda"),
+                ref c => syntax_error(&format!("'case-lambda' clause must be a list of params and a body, got {}", c.write(&self.st))),
+            }
+        }).collect::<Result<Vec<Clause>, EvalError>>()?;
+        Ok(Data::DFun(Fun::User(Rc::new(Lambda{name: None, clauses, env: env.clone()}))))
+    }
+
+    fn clause(&self, params: Params, body: &[SExp], form: &str) -> Result<Clause, EvalError> {
+        if body.is_empty() {
+            return syntax_error(&format!("missing function body in '{}'", form));
+        }
+        Ok(Clause{params, body: body.to_vec()})
+    }
+
+    /// A param list, or a single symbol to be bound to a list of all the arguments
+    fn formals(&self, formals: &SExp, form: &str) -> Result<Params, EvalError> {
+        match *formals {
+            SExp::List(ref l, _) => self.params(l, form),
+            SExp::Sym(rest, _) if !self.is_param_marker(rest) => Ok(Params{rest: Some(self.variable(rest, form)?), ..Params::default()}),
+            ref s => syntax_error(&format!("'{}' definition requires an argument list: {}", form, s.write(&self.st))),
+        }
+    }
+
+    fn is_param_marker(&self, s: Symbol) -> bool {
+        s == self.sym_optional || s == self.sym_rest || s == self.sym_key || s == self.sym_dot
+    }
+
+    fn params(&self, l: &[SExp], form: &str) -> Result<Params, EvalError> {
+        // the sections, in the order they must appear
+        const REQUIRED: usize = 0;
+        const OPTIONAL: usize = 1;
+        const REST: usize = 2;
+        const KEY: usize = 3;
+        let mut params = Params::default();
+        let mut section = REQUIRED;
+        let mut names: Vec<Symbol> = vec!();
+        let mut iter = l.iter();
+        while let Some(p) = iter.next() {
+            match *p {
+                SExp::Sym(s, _) if s == self.sym_dot => {
+                    match (iter.next(), iter.next()) {
+                        (Some(&SExp::Sym(rest, _)), None) if section < REST && !self.is_param_marker(rest) => {
+                            params.rest = Some(rest);
+                            names.push(rest);
+                        },
+                        _ => return syntax_error("'.' in a param list must be followed by just the rest param"),
                     }
+                },
+                SExp::Sym(s, _) if self.is_param_marker(s) => {
+                    let next = if s == self.sym_optional { OPTIONAL } else if s == self.sym_rest { REST } else { KEY };
+                    if next <= section {
+                        return syntax_error(&format!("{} is out of place in param list", s.write(&self.st)));
+                    }
+                    section = next;
+                    if section == REST {
+                        match iter.next() {
+                            Some(&SExp::Sym(rest, _)) if !self.is_param_marker(rest) => {
+                                params.rest = Some(rest);
+                                names.push(rest);
+                            },
+                            _ => return syntax_error("'#!rest' must be followed by the rest param"),
+                        }
+                    }
+                },
+                SExp::Sym(s, _) if section != REST => {
+                    match section {
+                        REQUIRED => params.required.push(s),
+                        OPTIONAL => params.optional.push((s, None)),
+                        _ => params.keys.push((s, self.keyword(s), None)),
+                    }
+                    names.push(s);
+                },
+                SExp::List(ref l, _) if section == OPTIONAL || section == KEY => {
+                    match l.as_slice() {
+                        [SExp::Sym(s, _), default] if !self.is_param_marker(*s) => {
+                            if section == OPTIONAL {
+                                params.optional.push((*s, Some(default.clone())));
+                            } else {
+                                params.keys.push((*s, self.keyword(*s), Some(default.clone())));
+                            }
+                            names.push(*s);
+                        },
+                        _ => return syntax_error(&format!("param with a default must be a name and an expression: {}", p.write(&self.st))),
+                    }
+                },
+                ref e => return syntax_error(&format!("unexpected entry in param list: {}", e.write(&self.st))),
+            }
+        }
+        for name in &names {
+            self.variable(*name, form)?;
+        }
+        names.sort();
+        for pair in names.windows(2) {
+            if pair[0] == pair[1] {
+                return syntax_error(&format!("duplicate param {}", pair[0].write(&self.st)));
+            }
+        }
+        Ok(params)
+    }
+
+    /// A function which was created just to be bound to `name` is named after it
+    fn named(mut val: Data, name: Symbol) -> Data {
+        if let Data::DFun(Fun::User(ref mut lambda)) = val {
+            if let Some(lambda) = Rc::get_mut(lambda) {
+                lambda.name = Some(name);
+            }
+        }
+        val
+    }
+
+    /// `(define name expr)`, or `(define (name param...) body...)` to define a function, binding
+    /// the name in the innermost environment.  Within a function body, that is the environment
+    /// of the call.
+    fn form_define(&self, mut args: Iter<SExp>, env: &Rc<Env>) -> EvalResult {
+        let (name, val) = match args.next() {
+            None => return syntax_error("missing variable name in 'define'"),
+            Some(SExp::Sym(name, _)) => {
+                match (args.next(), args.next()) {
+                    (None, _) => return syntax_error("missing variable value in 'define'"),
+                    (Some(s), None) => (self.variable(*name, "define")?, self.eval_in(s, env)?),
+                    (Some(_), Some(_)) => return syntax_error("too many values in 'define'"),
+                }
+            },
+            Some(SExp::List(ref l, _)) => {
+                match l.split_first() {
+                    Some((SExp::Sym(name, _), params)) => {
+                        let clause = self.clause(self.params(params, "define")?, args.as_slice(), "define")?;
+                        (self.variable(*name, "define")?, Data::DFun(Fun::User(Rc::new(Lambda{name: None, clauses: vec!(clause), env: env.clone()}))))
+                    },
+                    _ => return syntax_error("'define' of a function must start with a list of its name and params"),
+                }
+            },
+            Some(s) => return syntax_error(&format!("'define' variable name must be a symbol, got: {}", s.write(&self.st))),
+        };
+        env.define(name, Interpreter::named(val, name));
+        Ok(Data::DExp(SExp::Nil))
+    }
+
+    /// `(set! name expr)` changes the value of the existing binding of `name` in whichever
+    /// environment it is found, so that the change is seen by every closure sharing that binding
+    fn form_set(&self, mut args: Iter<SExp>, env: &Rc<Env>) -> EvalResult {
+        match (args.next(), args.next(), args.next()) {
+            (Some(SExp::Sym(name, _)), Some(s), None) => {
+                let name = self.variable(*name, "set!")?;
+                let val = self.eval_in(s, env)?;
+                let mut env = Some(env.clone());
+                while let Some(envref) = env {
+                    if let Some(d) = envref.vars.borrow_mut().get_mut(&name) {
+                        *d = Some(val);
+                        return Ok(Data::DExp(SExp::Nil));
+                    }
+                    env = envref.parent.clone();
+                }
+                Err(EvalError::with_value(ErrorKind::UnboundVariable, Data::DExp(SExp::Sym(name, None)), format!("cannot set undefined {}", name.write(&self.st))))
+            },
+            (Some(SExp::Sym(_, _)), _, _) => syntax_error("'set!' requires a variable name and a single value"),
+            (Some(s), _, _) => syntax_error(&format!("'set!' variable name must be a symbol, got: {}", s.write(&self.st))),
+            (None, _, _) => syntax_error("missing variable name in 'set!'"),
+        }
+    }
+
+    /// The `((name init) ...)` list of a binding form
+    fn bindings<'s>(&self, form: &str, s: Option<&'s SExp>) -> Result<Vec<(Symbol, &'s SExp)>, EvalError> {
+        match s {
+            Some(SExp::List(ref l, _)) => l.iter().map(|b| {
+                match *b {
+                    SExp::List(ref pair, _) => match pair.as_slice() {
+                        [SExp::Sym(name, _), init] => Ok((self.variable(*name, form)?, init)),
+                        _ => syntax_error(&format!("'{}' binding must be a variable name and a value, got: {}", form, b.write(&self.st))),
+                    },
+                    _ => syntax_error(&format!("'{}' binding must be a list, got: {}", form, b.write(&self.st))),
+                }
+            }).collect(),
+            Some(s) => syntax_error(&format!("'{}' requires a list of bindings, got: {}", form, s.write(&self.st))),
+            None => syntax_error(&format!("missing bindings in '{}'", form)),
+        }
+    }
+
+    /// `(let ((name init) ...) body...)` evaluates the inits in `env`, and the body in a child
+    /// environment binding their values.  Named let, `(let loop ((name init) ...) body...)`,
+    /// also binds `loop` in the body to a function taking the names as params and having the
+    /// body as its own, and starts by calling it with the inits.
+    fn form_let(&self, mut args: Iter<SExp>, span: Option<Span>, env: &Rc<Env>) -> TailResult {
+        let loop_name = match args.as_slice().first() {
+            Some(SExp::Sym(name, _)) => {
+                args.next();
+                Some(self.variable(*name, "let")?)
+            },
+            _ => None,
+        };
+        let bindings = self.bindings("let", args.next())?;
+        let body = args.as_slice();
+        if body.is_empty() {
+            return syntax_error("missing body in 'let'");
+        }
+        let vals = bindings.iter().map(|&(_, init)| self.eval_in(init, env) ).collect::<Result<Vec<Data>, EvalError>>()?;
+        let new_env = Rc::new(Env::new(Some(env.clone())));
+        match loop_name {
+            None => {
+                for (&(name, _), val) in bindings.iter().zip(vals) {
+                    new_env.define(name, Interpreter::named(val, name));
+                }
+                self.eval_body(body, &new_env)
+            },
+            Some(loop_name) => {
+                let params = Params{required: bindings.iter().map(|&(name, _)| name ).collect(), ..Params::default()};
+                let clause = Clause{params, body: body.to_vec()};
+                let lambda = Rc::new(Lambda{name: Some(loop_name), clauses: vec!(clause), env: new_env.clone()});
+                new_env.define(loop_name, Data::DFun(Fun::User(lambda.clone())));
+                Ok(Tail::Call(Fun::User(lambda), vals, span))
+            },
+        }
+    }
+
+    /// Like `let`, but each init is evaluated in an environment binding those before it
+    fn form_let_star(&self, mut args: Iter<SExp>, env: &Rc<Env>) -> TailResult {
+        let bindings = self.bindings("let*", args.next())?;
+        let body = args.as_slice();
+        if body.is_empty() {
+            return syntax_error("missing body in 'let*'");
+        }
+        let mut env = env.clone();
+        for (name, init) in bindings {
+            let val = self.eval_in(init, &env)?;
+            env = Rc::new(Env::new(Some(env)));
+            env.define(name, Interpreter::named(val, name));
+        }
+        // so that definitions in the body never land in the enclosing environment
+        let env = Rc::new(Env::new(Some(env)));
+        self.eval_body(body, &env)
+    }
+
+    /// `letrec` if `sequential` is false, else `letrec*`.  The inits are evaluated in the new
+    /// environment, so functions they create can refer to each other.  All the names are bound
+    /// before any init is evaluated, but using one before it has a value is an error.  With
+    /// `letrec`, values are assigned once all the inits have been evaluated, while `letrec*`
+    /// assigns each in turn, so that later inits can use the values of earlier ones.
+    fn form_letrec(&self, mut args: Iter<SExp>, env: &Rc<Env>, sequential: bool) -> TailResult {
+        let form = if sequential { "letrec*" } else { "letrec" };
+        let bindings = self.bindings(form, args.next())?;
+        let body = args.as_slice();
+        if body.is_empty() {
+            return syntax_error(&format!("missing body in '{}'", form));
+        }
+        let new_env = Rc::new(Env::new(Some(env.clone())));
+        new_env.vars.borrow_mut().extend(bindings.iter().map(|&(name, _)| (name, None) ));
+        let mut vals = vec!();
+        for &(name, init) in &bindings {
+            let val = Interpreter::named(self.eval_in(init, &new_env)?, name);
+            if sequential {
+                new_env.define(name, val);
+            } else {
+                vals.push((name, val));
+            }
+        }
+        for (name, val) in vals {
+            new_env.define(name, val);
+        }
+        self.eval_body(body, &new_env)
+    }
+
+    fn form_quote(&self, mut args: Iter<SExp>) -> EvalResult {
+        match (args.next(), args.next()) {
+            (Some(datum), None) => Ok(Data::from_sexp(datum)),
+            _ => syntax_error("'quote' requires exactly one argument"),
+        }
+    }
+
+    fn form_quasiquote(&self, mut args: Iter<SExp>, env: &Rc<Env>) -> EvalResult {
+        match (args.next(), args.next()) {
+            (Some(template), None) => self.quasi(template, 1, env),
+            _ => syntax_error("'quasiquote' requires exactly one argument"),
+        }
+    }
+
+    /// Expands a quasiquote template.  `depth` counts the enclosing `quasiquote` forms, less
+    /// the enclosing `unquote` forms; only unquoted expressions reached at depth 1 are
+    /// evaluated, and those within a nested quasiquote are kept as data.
+    fn quasi(&self, template: &SExp, depth: usize, env: &Rc<Env>) -> EvalResult {
+        match *template {
+            SExp::List(ref l, _) => {
+                match l.first() {
+                    Some(SExp::Sym(s, _)) if (s == &self.sym_unquote || s == &self.sym_unquote_splicing) && depth == 1 => {
+                        if s == &self.sym_unquote && l.len() == 2 {
+                            self.eval_in(&l[1], env)
+                        } else if s == &self.sym_unquote {
+                            syntax_error("'unquote' requires exactly one argument")
+                        } else {
+                            syntax_error("'unquote-splicing' may only appear within a list, and requires exactly one argument")
+                        }
+                    },
+                    Some(SExp::Sym(s, _)) if s == &self.sym_unquote || s == &self.sym_unquote_splicing => {
+                        self.quasi_list(&l[1..], depth - 1, env).map(|mut rest| {
+                            rest.insert(0, Data::DExp(SExp::Sym(*s, None)));
+                            Data::DList(rest)
+                        })
+                    },
+                    Some(SExp::Sym(s, _)) if s == &self.sym_quasiquote => {
+                        self.quasi_list(&l[1..], depth + 1, env).map(|mut rest| {
+                            rest.insert(0, Data::DExp(SExp::Sym(*s, None)));
+                            Data::DList(rest)
+                        })
+                    },
+                    _ => self.quasi_list(l, depth, env).map(Data::DList),
                 }
+            },
+            ref atom => Ok(Data::from_sexp(atom)),
+        }
+    }
+
+    /// Expands the items of a list within a quasiquote template, splicing in the values of any
+    /// `unquote-splicing` forms at depth 1
+    fn quasi_list(&self, items: &[SExp], depth: usize, env: &Rc<Env>) -> Result<Vec<Data>, EvalError> {
+        let mut result = vec!();
+        for item in items {
+            match *item {
+                SExp::List(ref l, _) if depth == 1 && l.len() == 2 && l[0] == SExp::Sym(self.sym_unquote_splicing, None) => {
+                    match self.eval_in(&l[1], env)? {
+                        Data::DList(values) => result.extend(values),
+                        v => return Err(EvalError::with_value(ErrorKind::TypeError, v, "'unquote-splicing' requires a list value".to_string())),
+                    }
+                },
+                _ => result.push(self.quasi(item, depth, env)?),
             }
-            s => panic!("let variable name must be a symbol, got: {:?}", s)
         }
+        Ok(result)
     }
 
-    fn user_call(&self, args: &[Data], params: &[SymbolRef], body: &SExp) -> Data {
-        let env_old = self.env.borrow().clone();
-        let new_env = Rc::new(Env::new(Some(env_old.clone())));
-        {
-            let mut h = new_env.vars.borrow_mut();
-            for (p, a) in params.iter().zip(args) {
-                h.insert(p.clone(), a.clone());
+    /// Creates the environment for a call to a user function, as a fresh child of the
+    /// environment the function closed over.  Defaults are evaluated in that environment, once
+    /// the params before them are bound, and an optional or key param with no default and no
+    /// argument is bound to nil.
+    fn bind_args(&self, lambda: &Lambda, params: &Params, args: Vec<Data>) -> Result<Rc<Env>, EvalError> {
+        if !params.accepts(args.len()) {
+            let (min, max) = (params.required.len(), params.required.len() + params.optional.len());
+            let expected = if params.rest.is_some() || !params.keys.is_empty() {
+                format!("at least {}", min)
+            } else if min == max {
+                format!("{}", min)
+            } else {
+                format!("{} to {}", min, max)
+            };
+            return Err(EvalError::new(ErrorKind::ArityError, format!("{} expected {} arguments, got {}", lambda.describe(&self.st), expected, args.len())));
+        }
+        let new_env = Rc::new(Env::new(Some(lambda.env.clone())));
+        let mut args = args.into_iter().peekable();
+        for p in &params.required {
+            new_env.define(*p, args.next().unwrap());
+        }
+        for &(p, ref default) in &params.optional {
+            // with key params, a keyword ends the positional arguments
+            let positional = match args.peek() {
+                Some(Data::DExp(SExp::Sym(s, _))) => params.keys.is_empty() || !self.st.is_keyword(*s),
+                Some(_) => true,
+                None => false,
+            };
+            let val = if positional {
+                args.next().unwrap()
+            } else {
+                self.default_value(default, &new_env)?
+            };
+            new_env.define(p, val);
+        }
+        let remaining: Vec<Data> = args.collect();
+        if !params.keys.is_empty() {
+            let mut given: SymbolMap<Data> = SymbolMap::default();
+            let mut pairs = remaining.iter();
+            while let Some(k) = pairs.next() {
+                let key = match *k {
+                    Data::DExp(SExp::Sym(s, _)) => params.keys.iter()
+                        .find(|&&(_, keyword, _)| keyword == s )
+                        .map(|&(p, _, _)| p ),
+                    _ => None,
+                };
+                match key {
+                    Some(p) => match pairs.next() {
+                        Some(v) => { given.insert(p, v.clone()); },
+                        None => return Err(EvalError::with_value(ErrorKind::ArityError, k.clone(), format!("missing value for keyword {} passed to {}", k.write(&self.st), lambda.describe(&self.st)))),
+                    },
+                    // anything else is left for the rest param, if there is one
+                    None if params.rest.is_some() => {},
+                    None => return Err(EvalError::with_value(ErrorKind::TypeError, k.clone(), format!("unexpected argument {} for {}, expected a keyword", k.write(&self.st), lambda.describe(&self.st)))),
+                }
+            }
+            for &(p, _, ref default) in &params.keys {
+                let val = match given.remove(&p) {
+                    Some(v) => v,
+                    None => self.default_value(default, &new_env)?,
+                };
+                new_env.define(p, val);
             }
-            println!("args: {:?}", h);
         }
-        *self.env.borrow_mut() = new_env;
-        let result = self.eval_sexp(body);
-        *self.env.borrow_mut() = env_old;
-        result
+        if let Some(rest) = params.rest {
+            new_env.define(rest, Data::DList(remaining));
+        }
+        Ok(new_env)
+    }
+
+    fn default_value(&self, default: &Option<SExp>, env: &Rc<Env>) -> EvalResult {
+        match *default {
+            Some(ref e) => self.eval_in(e, env),
+            None => Ok(Data::DExp(SExp::Nil)),
+        }
     }
 
-    pub fn define_native<CB: 'static + Fn(&[Data])->Data>(&self, name: SymbolRef, c: CB) {
-        let env = self.env.borrow();
-        env.vars.borrow_mut().insert(name.clone(), Data::DFun(Fun::Native{name: name, code: Rc::new(Box::new(c))}));
+    /// Calls the function `f` with the given arguments, as for a call written in code.  This is
+    /// how native functions call the functions passed to them.
+    pub fn apply_fun(&self, f: &Data, args: Vec<Data>) -> EvalResult {
+        self.call(self.callable(f.clone())?, args, None)
+    }
+
+    pub fn define_native<CB: 'static + Fn(&Interpreter, &[Data])->EvalResult>(&self, name: Symbol, c: CB) {
+        self.env.define(name, Data::DFun(Fun::Native{name, code: Rc::new(Box::new(c))}));
+    }
+
+    /// Defines a native function from a Rust function taking `FromData` params and returning
+    /// something `IntoData`, as in `register("add", |a: i64, b: i64| a + b)`.  Calls are checked
+    /// against the params' number and types, and trailing `Option` params may be left out.
+    pub fn register<P, F: NativeFn<P>>(&self, name: &str, f: F) {
+        let sym = self.st.sym_for(name);
+        let name = name.to_string();
+        self.define_native(sym, move |_, args:&[Data]| f.call(&name, args) );
     }
 }
 
@@ -250,43 +1124,62 @@ mod tests {
     use super::Interpreter;
     use builtin;
     use super::Data;
+    use super::EvalError;
+    use super::EvalResult;
+    use super::ErrorKind;
     use symtable::SymTable;
+    use number::Number;
     use parse::SExp;
     use parse::Parser;
+    use print::Render;
     use std::rc::Rc;
     use std::cell::Cell;
+    use std::thread;
+
+    fn eval_text(text: &str) -> EvalResult {
+        let st = SymTable::new();
+        let i = Interpreter::new(&st);
+        builtin::init(&st, &i);
+        let mut parse = Parser::new(&st, text.chars().peekable());
+        let code = parse.compilation_unit();
+        if let Ok(SExp::List(l, _)) = code {
+            i.eval_expressions(&l)
+        } else {
+            panic!("unexpected parse result {:?}", code);
+        }
+    }
 
     #[test]
     fn fun_call() {
         let st = SymTable::new();
         let i = Interpreter::new(&st);
         let myfun_sym = st.sym_for("myfun");
-        let call_args = vec!(SExp::Sym(myfun_sym.clone()), SExp::LString("hello".to_string()));
-        let expected_args = vec!(Data::DExp(SExp::LString("hello".to_string())));
-        let call = SExp::List(call_args);
+        let call_args = vec!(SExp::Sym(myfun_sym, None), SExp::LString("hello".to_string(), None));
+        let expected_args = vec!(Data::DExp(SExp::LString("hello".to_string(), None)));
+        let call = SExp::List(call_args, None);
         let called = Rc::new(Cell::new(false));
         let called_clone = called.clone();
-        i.define_native(myfun_sym, move |args:&[Data]| {
+        i.define_native(myfun_sym, move |_, args:&[Data]| {
             assert_eq!(expected_args, args);
             called_clone.set(true);
-            Data::DExp(SExp::Nil)
+            Ok(Data::DExp(SExp::Nil))
         });
-        i.eval_sexp(&call);
+        i.eval_sexp(&call).unwrap();
         assert!(called.get());
     }
 
     #[test]
     fn fun_call_user() {
-        let text = "(let succ (lambda (x) (plus x 1)))
+        let text = "(define succ (lambda (x) (plus x 1)))
                     (succ 1)";
         let st = SymTable::new();
         let i = Interpreter::new(&st);
         builtin::init(&st, &i);
-        let mut parse = Parser::new(st, text.chars().peekable());
+        let mut parse = Parser::new(&st, text.chars().peekable());
         let code = parse.compilation_unit();
-        if let Ok(SExp::List(l)) = code {
+        if let Ok(SExp::List(l, _)) = code {
             let result = i.eval_expressions(&l);
-            assert_eq!(Data::DExp(SExp::Num(2)), result);
+            assert_eq!(Ok(Data::DExp(SExp::Num(Number::Int(2), None))), result);
         } else {
             panic!("unexpected parse result {:?}", code);
         }
@@ -297,12 +1190,12 @@ mod tests {
         let st = SymTable::new();
         let interpreter = Interpreter::new(&st);
         let code = SExp::List(vec!(
-            SExp::Sym(st.sym_for("if")),
-            SExp::Boolean(true),
-            SExp::Num(1),
-            SExp::Num(2),
-        ));
-        assert_eq!(Data::DExp(SExp::Num(1)), interpreter.eval_sexp(&code))
+            SExp::Sym(st.sym_for("if"), None),
+            SExp::Boolean(true, None),
+            SExp::Num(Number::Int(1), None),
+            SExp::Num(Number::Int(2), None),
+        ), None);
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(1), None)), interpreter.eval_sexp(&code).unwrap())
     }
 
     #[test]
@@ -310,12 +1203,12 @@ mod tests {
         let st = SymTable::new();
         let interpreter = Interpreter::new(&st);
         let code = SExp::List(vec!(
-            SExp::Sym(st.sym_for("if")),
-            SExp::Boolean(false),
-            SExp::Num(1),
-            SExp::Num(2),
-        ));
-        assert_eq!(Data::DExp(SExp::Num(2)), interpreter.eval_sexp(&code))
+            SExp::Sym(st.sym_for("if"), None),
+            SExp::Boolean(false, None),
+            SExp::Num(Number::Int(1), None),
+            SExp::Num(Number::Int(2), None),
+        ), None);
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(2), None)), interpreter.eval_sexp(&code).unwrap())
     }
 
     #[test]
@@ -323,10 +1216,581 @@ mod tests {
         let st = SymTable::new();
         let interpreter = Interpreter::new(&st);
         let code = SExp::List(vec!(
-            SExp::Sym(st.sym_for("if")),
-            SExp::Boolean(false),
-            SExp::Num(1),
-        ));
-        assert_eq!(Data::DExp(SExp::Nil), interpreter.eval_sexp(&code))
+            SExp::Sym(st.sym_for("if"), None),
+            SExp::Boolean(false, None),
+            SExp::Num(Number::Int(1), None),
+        ), None);
+        assert_eq!(Data::DExp(SExp::Nil), interpreter.eval_sexp(&code).unwrap())
+    }
+
+    #[test]
+    fn curried_adder() {
+        let result = eval_text("(define adder (lambda (a) (lambda (b) (plus a b))))
+                                (define addtwo (adder 2))
+                                (define addten (adder 10))
+                                (plus (addtwo 1) (addten 1))").unwrap();
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(14), None)), result);
+    }
+
+    #[test]
+    fn closure_ignores_caller_bindings() {
+        // under dynamic scoping, 'n' would resolve to the caller's parameter rather than the
+        // value captured when the closure was made
+        let result = eval_text("(define make (lambda (n) (lambda () n)))
+                                (define getn (make 1))
+                                (define callwith (lambda (n) (getn)))
+                                (callwith 99)").unwrap();
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(1), None)), result);
+    }
+
+    #[test]
+    fn counter_closures() {
+        // each counter closes over its own 'count', and hands back its successor
+        let result = eval_text("(define counter (lambda (count) (lambda (next) (if next (counter (plus count 1)) count))))
+                                (define a (counter 0))
+                                (define b (counter 100))
+                                (define a (a #t))
+                                (define a (a #t))
+                                (define b (b #t))
+                                (plus (a #f) (b #f))").unwrap();
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(103), None)), result);
+    }
+
+    /// Evaluates each top-level expression separately, so that results can be compared with
+    /// each other (symbols from different symbol tables can't be compared)
+    fn eval_each(text: &str) -> Vec<EvalResult> {
+        let st = SymTable::new();
+        eval_each_in(&st, text)
+    }
+
+    fn eval_each_in(st: &SymTable, text: &str) -> Vec<EvalResult> {
+        let i = Interpreter::new(st);
+        builtin::init(st, &i);
+        let mut parse = Parser::new(st, text.chars().peekable());
+        match parse.compilation_unit() {
+            Ok(SExp::List(l, _)) => l.iter().map(|e| i.eval_sexp(e) ).collect(),
+            other => panic!("unexpected parse result {:?}", other),
+        }
+    }
+
+    /// Like `eval_each`, with each value written as text
+    fn write_each(text: &str) -> Vec<Result<String, EvalError>> {
+        let st = SymTable::new();
+        eval_each_in(&st, text).into_iter().map(|r| r.map(|d| d.write(&st).to_string() ) ).collect()
+    }
+
+    /// Each written value, or an error as its kind and message, so a whole run can be compared
+    /// at once
+    fn to_strings(results: Vec<Result<String, EvalError>>) -> Vec<String> {
+        results.into_iter().map(|r| r.unwrap_or_else(|e| format!("{:?}: {}", e.kind, e.msg) ) ).collect()
+    }
+
+    fn eval_err(text: &str) -> ErrorKind {
+        match eval_text(text) {
+            Err(e) => e.kind,
+            Ok(d) => panic!("expected '{}' to fail, but got {:?}", text, d),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(ErrorKind::UnboundVariable, eval_err("(plus nosuchthing 1)"));
+        assert_eq!(ErrorKind::NotCallable, eval_err("(define x 1) (x 2)"));
+        assert_eq!(ErrorKind::TypeError, eval_err("(if 1 2 3)"));
+        assert_eq!(ErrorKind::TypeError, eval_err("(plus 1 \"two\")"));
+        assert_eq!(ErrorKind::ArityError, eval_err("(plus 1)"));
+        assert_eq!(ErrorKind::DivideByZero, eval_err("(div 1 0)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(lambda 1 x)"));
+    }
+
+    #[test]
+    fn error_value() {
+        let st = SymTable::new();
+        let i = Interpreter::new(&st);
+        let missing = st.sym_for("missing");
+        let err = i.eval_sexp(&SExp::Sym(missing, None)).unwrap_err();
+        assert_eq!(Some(Data::DExp(SExp::Sym(missing, None))), err.value.map(|v| *v ));
+    }
+
+    #[test]
+    fn recover_after_error() {
+        let st = SymTable::new();
+        let i = Interpreter::new(&st);
+        builtin::init(&st, &i);
+        let mut parse = Parser::new(&st, "(define a 1) (div a 0) (plus a 1)".chars().peekable());
+        if let Ok(SExp::List(l, _)) = parse.compilation_unit() {
+            assert!(i.eval_sexp(&l[0]).is_ok());
+            assert!(i.eval_sexp(&l[1]).is_err());
+            assert_eq!(Ok(Data::DExp(SExp::Num(Number::Int(2), None))), i.eval_sexp(&l[2]));
+        } else {
+            panic!("parse failed");
+        }
+    }
+
+    #[test]
+    fn error_span() {
+        let err = eval_text("(define f (lambda (x)\n  (plus x \"a\")))\n(f 1)").unwrap_err();
+        let span = err.span.expect("error should have a source location");
+        assert_eq!((2, 3), (span.start.line, span.start.col));
+
+        // a bare symbol is located by its own span rather than that of the enclosing form
+        let err = eval_text("(define f (lambda (x)\n  x\n    nosuch))\n(f 1)").unwrap_err();
+        assert_eq!(ErrorKind::UnboundVariable, err.kind);
+        let span = err.span.expect("error should have a source location");
+        assert_eq!((3, 5), (span.start.line, span.start.col));
+        assert_eq!((3, 11), (span.end.line, span.end.col));
+        let span = eval_text("1\n  nosuch").unwrap_err().span.unwrap();
+        assert_eq!((2, 3), (span.start.line, span.start.col));
+    }
+
+    #[test]
+    fn tail_call_countdown() {
+        let result = eval_text("(define countdown (lambda (n) (if (le n 0) \"done\" (countdown (minus n 1)))))
+                                (countdown 1000000)").unwrap();
+        assert_eq!(Data::DExp(SExp::LString("done".to_string(), None)), result);
+    }
+
+    #[test]
+    fn begin() {
+        assert_eq!(Ok(Data::DExp(SExp::Num(Number::Int(3), None))), eval_text("(begin (define a 1) (define b 2) (+ a b))"));
+        assert_eq!(Ok(Data::DExp(SExp::Nil)), eval_text("(begin)"));
+        assert_eq!(ErrorKind::UnboundVariable, eval_err("(begin (undefined-fn) 1)"));
+    }
+
+    #[test]
+    fn multi_expression_body() {
+        let r = eval_each("(define f (lambda (x) (define y (* x 2)) (+ y 1)))  (f 5)  y");
+        assert_eq!(Ok(Data::DExp(SExp::Num(Number::Int(11), None))), r[1]);
+        assert_eq!(ErrorKind::UnboundVariable, r[2].as_ref().unwrap_err().kind);
+        let r = write_each("(define loop (lambda (n) (define m (- n 1)) (if (= n 0) 'done (begin n (loop m)))))
+                            (loop 100000)");
+        assert_eq!("done", r[1].as_ref().unwrap());
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(lambda (x))"));
+    }
+
+    #[test]
+    fn and_or() {
+        let r = to_strings(write_each("(and) (or) (and #t 1) (and #f undefined) (or #f 2) (or #t undefined) (and #t #t #f)"));
+        assert_eq!(vec!("#t", "#f", "1", "#f", "2", "#t", "#f"), r);
+        assert_eq!(ErrorKind::TypeError, eval_err("(and 1 #t)"));
+        let result = eval_text("(define countdown (lambda (n) (or (le n 0) (countdown (minus n 1)))))
+                                (countdown 100000)").unwrap();
+        assert_eq!(Data::DExp(SExp::Boolean(true, None)), result);
+    }
+
+    #[test]
+    fn when_unless() {
+        let r = to_strings(write_each("(when #t 1 2) (when #f undefined) (unless #f 3) (unless #t undefined)"));
+        assert_eq!(vec!("2", "()", "3", "()"), r);
+        assert_eq!(ErrorKind::TypeError, eval_err("(when 1 2)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(unless)"));
+    }
+
+    #[test]
+    fn cond() {
+        let r = to_strings(write_each("(define sign (lambda (n) (cond ((< n 0) 'negative) ((= n 0) 'zero) (else 'positive))))
+                           (sign -3) (sign 0) (sign 7)
+                           (cond (#f 1) ((+ 1 2) => (lambda (x) (* x 2))))
+                           (cond ((= 1 1)))
+                           (cond (#f 1))"));
+        assert_eq!(vec!("()", "negative", "zero", "positive", "6", "#t", "()"), r);
+        let result = eval_text("(define countdown (lambda (n) (cond ((le n 0) \"done\") (else (countdown (minus n 1))))))
+                                (countdown 100000)").unwrap();
+        assert_eq!(Data::DExp(SExp::LString("done".to_string(), None)), result);
+        assert_eq!(ErrorKind::TypeError, eval_err("(cond (1 2))"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(cond (else 1) (#t 2))"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(cond ())"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(cond (1 =>))"));
+        assert_eq!(ErrorKind::NotCallable, eval_err("(cond (1 => 2))"));
+    }
+
+    #[test]
+    fn case() {
+        let r = to_strings(write_each("(define kind (lambda (x) (case x ((1 2 3) 'small) ((a b) 'letter) ((\"s\" #t ()) 'other) (else 'unknown))))
+                           (kind 2) (kind 'b) (kind \"s\") (kind #t) (kind '()) (kind 2.0) (kind 'c)
+                           (case (* 2 3) ((6) => (lambda (x) (+ x 1))) (else 0))
+                           (case 9 ((1) 1) (else => (lambda (x) x)))
+                           (case 9 ((1) 1))"));
+        assert_eq!(vec!("()", "small", "letter", "other", "other", "other", "unknown", "unknown", "7", "9", "()"), r);
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(case)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(case 1 (1 2))"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(case 1 ((1)))"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(case 1 (else 1) ((1) 2))"));
+    }
+
+    #[test]
+    fn eqv() {
+        let r = to_strings(write_each("(eqv? 2 2) (eqv? 2 2.0) (eqv? 'a 'a) (eqv? \"a\" \"a\") (eqv? '() '()) (eqv? '(1) '(1)) (eqv? #t #f)"));
+        assert_eq!(vec!("#t", "#f", "#t", "#t", "#t", "#f", "#f"), r);
+    }
+
+    #[test]
+    fn define() {
+        let r = write_each("(define x 1)  (define (add a b) (+ (+ a b) x))  (add 2 3)  add
+                           (define (f) (define x 10) x)  (f)  x");
+        assert_eq!("6", r[2].as_ref().unwrap());
+        assert_eq!("#<procedure add>", r[3].as_ref().unwrap());
+        assert_eq!("10", r[5].as_ref().unwrap());
+        assert_eq!("1", r[6].as_ref().unwrap());
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(define x)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(define x 1 2)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(define 1 2)"));
+    }
+
+    #[test]
+    fn let_forms() {
+        let r = to_strings(write_each("(define x 1)
+                           (let ((x 2) (y x)) (+ x y))
+                           x
+                           (let* ((x 2) (y x)) (+ x y))
+                           (let () (define z 1) z)
+                           z
+                           (letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                                    (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+                             (even? 100))
+                           (letrec* ((a 1) (b (+ a 1))) b)
+                           (let ((f (lambda () 1))) f)"));
+        assert_eq!(vec!("()", "3", "1", "4", "1", "UnboundVariable: undefined z", "#t", "2", "#<procedure f>"), r);
+        assert_eq!(ErrorKind::UnboundVariable, eval_err("(letrec ((a 1) (b (+ a 1))) b)"));
+        // the inits must not see an outer binding of a name the letrec is about to assign
+        let err = eval_text("(define a 100) (letrec ((a 1) (b (+ a 1))) b)").unwrap_err();
+        assert_eq!((ErrorKind::UnboundVariable, "a used before it was assigned"), (err.kind, err.msg.as_str()));
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(2), None)), eval_text("(define a 100) (letrec* ((a 1) (b (+ a 1))) b)").unwrap());
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(let x 1)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(let ((x 1)))"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(let ((x)) x)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(let* (x) x)"));
+    }
+
+    #[test]
+    fn set() {
+        let r = to_strings(write_each("(define x 1)  (set! x (+ x 1))  x
+                           (define inc #f)
+                           (define get #f)
+                           (let ((count 0))
+                             (set! inc (lambda () (set! count (+ count 1)) count))
+                             (set! get (lambda () count)))
+                           (define (make-counter)
+                             (let ((count 0)) (lambda () (set! count (+ count 1)) count)))
+                           (define c (make-counter))
+                           (define d (make-counter))
+                           (inc) (inc) (get) (c) (c) (d)
+                           (let ((x 10)) (set! x 20) x)
+                           x"));
+        assert_eq!(vec!("()", "()", "2", "()", "()", "()", "()", "()", "()", "1", "2", "2", "1", "2", "1", "20", "2"), r);
+        assert_eq!(ErrorKind::UnboundVariable, eval_err("(set! nosuchthing 1)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(define x 1) (set! x)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(set! 1 2)"));
+    }
+
+    #[test]
+    fn operator_expressions() {
+        let r = to_strings(write_each("((lambda (x) (* x x)) 3)
+                           (define (make-adder a) (lambda (b) (+ a b)))
+                           ((make-adder 2) 3)
+                           (((lambda (a) (lambda (b) (- a b))) 10) 4)
+                           ((if #f + -) 3 1)
+                           ((let ((f (lambda () 'f))) f))"));
+        assert_eq!(vec!("9", "()", "5", "6", "2", "f"), r);
+        let r = write_each("(define (loop n) (if (= n 0) 'done ((lambda (m) (loop m)) (- n 1))))
+                            (loop 100000)");
+        assert_eq!("done", r[1].as_ref().unwrap());
+        assert_eq!(ErrorKind::NotCallable, eval_err("(1 2)"));
+        assert_eq!(ErrorKind::NotCallable, eval_err("(\"f\")"));
+        assert_eq!(ErrorKind::NotCallable, eval_err("((+ 1 2) 3)"));
+        assert_eq!(ErrorKind::UnboundVariable, eval_err("((nosuchthing) 1)"));
+    }
+
+    #[test]
+    fn arity() {
+        let r = eval_each("(define (f a b) b)  (f 1)  (f 1 2 3)  ((lambda () 1) 2)");
+        for r in &r[1..] {
+            assert_eq!(ErrorKind::ArityError, r.as_ref().unwrap_err().kind);
+        }
+        assert_eq!("'f' expected 2 arguments, got 1", r[1].as_ref().unwrap_err().msg);
+        assert_eq!("function expected 0 arguments, got 1", r[3].as_ref().unwrap_err().msg);
+        // too few arguments must not leave a param to be found in an enclosing scope
+        assert_eq!(ErrorKind::ArityError, eval_err("(define b 2) (define (f a b) b) (f 1)"));
+        let err = eval_text("(define (f a) a)\n(f)").unwrap_err();
+        assert_eq!(2, err.span.unwrap().start.line);
+    }
+
+    #[test]
+    fn rest_params() {
+        let r = to_strings(write_each("((lambda (a . rest) rest) 1 2 3)
+                           ((lambda (a . rest) rest) 1)
+                           ((lambda args args))
+                           ((lambda args args) 1 2)
+                           (define (f a #!rest r) `(,a ,@r))
+                           (f 1 2 3)
+                           (f)"));
+        assert_eq!(vec!("(2 3)", "()", "()", "(1 2)", "()", "(1 2 3)", "ArityError: 'f' expected at least 1 arguments, got 0"), r);
+        for text in &["(lambda (a .) a)", "(lambda (a . b c) a)", "(lambda (. a b) a)", "(lambda (#!rest) 1)", "(lambda (a a) a)", "(lambda (a . a) a)"] {
+            assert_eq!(ErrorKind::SyntaxError, eval_err(text), "for {}", text);
+        }
+    }
+
+    #[test]
+    fn optional_params() {
+        let r = to_strings(write_each("(define (f a #!optional (b (* a 2)) c) `(,a ,b ,c))
+                           (f 1)  (f 1 5)  (f 1 5 6)  (f)  (f 1 2 3 4)"));
+        assert_eq!(vec!("()", "(1 2 ())", "(1 5 ())", "(1 5 6)", "ArityError: 'f' expected 1 to 3 arguments, got 0",
+                        "ArityError: 'f' expected 1 to 3 arguments, got 4"), r);
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(lambda (#!optional a #!optional b) a)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(lambda (#!optional (a)) a)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(lambda ((a 1)) a)"));
+    }
+
+    #[test]
+    fn key_params() {
+        let r = to_strings(write_each("(define (f a #!optional (b 2) #!key (c 3) d) `(,a ,b ,c ,d))
+                           (f 1)  (f 1 d: 4)  (f 1 5 c: 6 d: 7)  (f 1 d: 7 c: 6)
+                           (define (g #!rest r #!key k) `(,k ,r))
+                           (g k: 1 2)
+                           x:"));
+        assert_eq!(vec!("()", "(1 2 3 ())", "(1 2 3 4)", "(1 5 6 7)", "(1 2 6 7)", "()", "(1 (k: 1 2))", "x:"), r);
+        assert_eq!(ErrorKind::TypeError, eval_err("(define (f #!key a) a) (f b: 1)"));
+        assert_eq!(ErrorKind::TypeError, eval_err("(define (f #!key a) a) (f 1)"));
+        assert_eq!(ErrorKind::ArityError, eval_err("(define (f #!key a) a) (f a:)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(lambda (#!key a #!optional b) a)"));
+        for text in &["(define a: 5)", "(define (a: x) x)", "(define (f a:) a:)", "(lambda k: 1)", "(lambda (#!key x:) 1)",
+                      "(set! a: 1)", "(let ((a: 1)) a:)", "(letrec ((a: 1)) a:)", "(let a: () 1)"] {
+            assert_eq!(ErrorKind::SyntaxError, eval_err(text), "for {}", text);
+        }
+    }
+
+    #[test]
+    fn case_lambda() {
+        let r = to_strings(write_each("(define area (case-lambda ((r) (* 3 (* r r))) ((w h) (* w h)) ((a b . more) more)))
+                           (area 2)  (area 2 3)  (area 1 2 3 4)  area
+                           ((case-lambda ((a) a)) 1 2)
+                           ((case-lambda ((a) a) ((a b) b)))"));
+        assert_eq!(vec!("()", "12", "6", "(3 4)", "#<procedure area>", "ArityError: function expected 1 arguments, got 2",
+                        "ArityError: no clause of function accepts 0 arguments"), r);
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(case-lambda (x))"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(case-lambda 1)"));
+    }
+
+    #[test]
+    fn recursion_limit() {
+        let text = "(define (f n) (if (= n 0) 0 (+ 1 (f (- n 1)))))  (f 100)  (f 100000)  (f 100)";
+        // with the stack of a main thread, for which the default limit is chosen
+        let r = thread::Builder::new().stack_size(8 << 20).spawn(move || to_strings(write_each(text)) ).unwrap().join().unwrap();
+        let exceeded = format!("RecursionLimit: recursion limit of {} exceeded", super::DEFAULT_RECURSION_LIMIT);
+        assert_eq!(vec!("()".to_string(), "100".to_string(), exceeded, "100".to_string()), r);
+
+        let st = SymTable::new();
+        let i = Interpreter::new(&st);
+        builtin::init(&st, &i);
+        i.set_recursion_limit(50);
+        let mut parse = Parser::new(&st, text.chars());
+        let r: Vec<EvalResult> = parse.by_ref().take(2).map(|e| i.eval_sexp(&e.unwrap()) ).collect();
+        assert_eq!(ErrorKind::RecursionLimit, r[1].as_ref().unwrap_err().kind);
+        assert_eq!(ErrorKind::RecursionLimit, eval_err("(define (f n) (if (= n 0) 0 (+ 1 (apply f (- n 1) '())))) (f 100000)"));
+    }
+
+    #[test]
+    fn native_callback() {
+        let st = SymTable::new();
+        let i = Interpreter::new(&st);
+        builtin::init(&st, &i);
+        i.define_native(st.sym_for("twice"), |interp, args:&[Data]| {
+            let once = interp.apply_fun(&args[0], vec!(args[1].clone()))?;
+            interp.apply_fun(&args[0], vec!(once))
+        });
+        let mut parse = Parser::new(&st, "(twice (lambda (x) (* x 3)) 2)  (twice 1 2)  (twice (lambda (x) (nosuchthing x)) 2)".chars());
+        let results: Vec<EvalResult> = parse.compilation_unit().map(|code| match code {
+            SExp::List(l, _) => l.iter().map(|e| i.eval_sexp(e) ).collect(),
+            other => panic!("unexpected parse result {:?}", other),
+        }).unwrap();
+        assert_eq!(Ok(Data::DExp(SExp::Num(Number::Int(18), None))), results[0]);
+        assert_eq!(ErrorKind::NotCallable, results[1].as_ref().unwrap_err().kind);
+        assert_eq!(ErrorKind::UnboundVariable, results[2].as_ref().unwrap_err().kind);
+    }
+
+    #[test]
+    fn higher_order_builtins() {
+        let r = to_strings(write_each("(map (lambda (x) (* x x)) '(1 2 3))
+                           (map + '(1 2 3) '(10 20))
+                           (map (lambda (x) x) '())
+                           (filter (lambda (x) (< x 3)) '(3 1 4 1 5 2))
+                           (sort '(3 1 4 1 5 9 2 6) <)
+                           (sort '(2 1.0 1 2.0) <)
+                           (apply + '(1 2))
+                           (apply + 1 '(2))
+                           (apply + 1 2 '())
+                           (define total 0)
+                           (for-each (lambda (x y) (set! total (+ total (* x y)))) '(1 2 3) '(4 5 6))
+                           total"));
+        assert_eq!(vec!("(1 4 9)", "(11 22)", "()", "(1 1 2)", "(1 1 2 3 4 5 6 9)", "(1.0 1 2 2.0)", "3", "3", "3", "()", "()", "32"), r);
+        assert_eq!(ErrorKind::TypeError, eval_err("(map (lambda (x) x) 1)"));
+        assert_eq!(ErrorKind::NotCallable, eval_err("(map 1 '(1))"));
+        assert_eq!(ErrorKind::TypeError, eval_err("(filter (lambda (x) x) '(1))"));
+        assert_eq!(ErrorKind::TypeError, eval_err("(sort '(1 2) +)"));
+        assert_eq!(ErrorKind::DivideByZero, eval_err("(sort '(1 0) (lambda (a b) (< (/ 1 a) (/ 1 b))))"));
+        assert_eq!(ErrorKind::ArityError, eval_err("(apply +)"));
+        assert_eq!(ErrorKind::ArityError, eval_err("(map (lambda (x y) x) '(1))"));
+    }
+
+    #[test]
+    fn named_let() {
+        let result = eval_text("(let loop ((i 0) (acc 0))
+                                  (if (= i 100000) acc (loop (+ i 1) (+ acc 2))))").unwrap();
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(200000), None)), result);
+        let r = write_each("(define (make) (let loop ((n 3)) (if (= n 0) loop (loop (- n 1)))))  (make)  loop");
+        assert_eq!("#<procedure loop>", r[1].as_ref().unwrap());
+        assert_eq!(ErrorKind::UnboundVariable, r[2].as_ref().unwrap_err().kind);
+    }
+
+    #[test]
+    fn mutual_tail_calls() {
+        let result = eval_text("(define iseven (lambda (n) (if (le n 0) #t (isodd (minus n 1)))))
+                                (define isodd (lambda (n) (if (le n 0) #f (iseven (minus n 1)))))
+                                (iseven 100001)").unwrap();
+        assert_eq!(Data::DExp(SExp::Boolean(false, None)), result);
+    }
+
+    #[test]
+    fn operator_names() {
+        let result = eval_text("(define square-then-halve (lambda (x) (/ (* x x) 2)))
+                                (if (<= (square-then-halve -4) 8) (- 10 (+ 1 2)) 0)").unwrap();
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(7), None)), result);
+    }
+
+    #[test]
+    fn mixed_arithmetic() {
+        assert_eq!(Data::DExp(SExp::Num(Number::Real(3.5), None)), eval_text("(+ 1 2.5)").unwrap());
+        assert_eq!(Data::DExp(SExp::Num(Number::Real(-0.25), None)), eval_text("(/ -1 4.0)").unwrap());
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(3000000000), None)), eval_text("(* 3 1000000000)").unwrap());
+        assert_eq!(Data::DExp(SExp::Boolean(true, None)), eval_text("(= 2 2.0)").unwrap());
+        assert_eq!(Data::DExp(SExp::Boolean(true, None)), eval_text("(< 1e-9 1)").unwrap());
+    }
+
+    #[test]
+    fn factorial_promotes_to_bignum() {
+        let result = eval_text("(define fact (lambda (n acc) (if (<= n 1) acc (fact (- n 1) (* n acc)))))
+                                (fact 30 1)").unwrap();
+        if let Data::DExp(SExp::Num(Number::Big(ref b), _)) = result {
+            assert_eq!("265252859812191058636308480000000", b.to_string());
+        } else {
+            panic!("unexpected result {:?}", result);
+        }
+        let result = eval_text("(define fact (lambda (n acc) (if (<= n 1) acc (fact (- n 1) (* n acc)))))
+                                (/ (fact 25 1) (fact 24 1))").unwrap();
+        assert_eq!(Data::DExp(SExp::Num(Number::Int(25), None)), result);
+        // neither part of this ratio fits in a float, though the ratio itself does
+        let result = eval_text("(define fact (lambda (n acc) (if (<= n 1) acc (fact (- n 1) (* n acc)))))
+                                (+ 0.0 (/ (fact 200 1) (+ (fact 200 1) 1)))").unwrap();
+        assert_eq!(Data::DExp(SExp::Num(Number::Real(1.0), None)), result);
+    }
+
+    #[test]
+    fn rationals() {
+        let num = |text| {
+            match eval_text(text).unwrap() {
+                Data::DExp(SExp::Num(n, _)) => n,
+                other => panic!("expected a number, got {:?}", other),
+            }
+        };
+        assert_eq!(Number::parse("1/3").unwrap(), num("(div 1 3)"));
+        assert_eq!(Number::Int(1), num("(+ 1/3 2/3)"));
+        assert_eq!(Number::Int(3), num("(numerator 6/8)"));
+        assert_eq!(Number::Int(4), num("(denominator 6/8)"));
+        assert_eq!(Number::Real(0.25), num("(exact->inexact 1/4)"));
+        assert_eq!(Number::Int(-2), num("(floor -3/2)"));
+        assert_eq!(Number::Int(-1), num("(truncate -3/2)"));
+        assert_eq!(Number::Int(2), num("(round 5/2)"));
+        assert_eq!(ErrorKind::TypeError, eval_text("(numerator 0.5)").unwrap_err().kind);
+        assert_eq!(vec!("TypeError: invalid arguments for 'numerator', expected an exact number", "ArityError: 'floor' expected 1 arguments, got 2",
+                        "TypeError: invalid arguments for 'lt', expected a number", "DivideByZero: '/' by zero"),
+                   to_strings(write_each("(numerator 0.5)  (floor 1 2)  (lt 1 \"2\")  (div 1 0)")));
+    }
+
+    #[test]
+    fn procedure_names() {
+        let r = write_each("(define sq (lambda (x) (* x x)))  sq  (define sq2 sq)  sq2  (lambda (x) x)  +");
+        let names: Vec<String> = r.into_iter().map(|d| d.unwrap() ).collect();
+        assert_eq!(vec!("()", "#<procedure sq>", "()", "#<procedure sq>", "#<procedure>", "#<procedure +>"), names);
+    }
+
+    #[test]
+    fn uninterned_symbols() {
+        let text = "(gensym)  (gensym \"loop\")  (string->uninterned-symbol \"x\")  'x  'g1";
+        let names: Vec<String> = write_each(text).into_iter().map(|d| d.unwrap() ).collect();
+        assert_eq!(vec!("#:g1", "#:loop2", "#:x", "x", "g1"), names);
+        let r = eval_each(text);
+        assert_ne!(r[0], r[4]);
+        assert_ne!(r[2], r[3]);
+        assert_eq!(ErrorKind::TypeError, eval_err("(gensym 1)"));
+        assert_eq!(ErrorKind::ArityError, eval_err("(gensym \"a\" \"b\")"));
+        assert_eq!(ErrorKind::TypeError, eval_err("(string->uninterned-symbol 'x)"));
+    }
+
+    #[test]
+    fn quote() {
+        let r = eval_each("(quote (a 1 \"s\" (b) ()))  'x  'x  '()");
+        if let Ok(Data::DList(ref l)) = r[0] {
+            assert_eq!(5, l.len());
+            assert_eq!(Data::DExp(SExp::Num(Number::Int(1), None)), l[1]);
+            assert_eq!(Data::DList(vec!()), l[4]);
+        } else {
+            panic!("unexpected result {:?}", r[0]);
+        }
+        assert_eq!(r[1], r[2]);
+        assert_eq!(Ok(Data::DList(vec!())), r[3]);
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(quote)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(quote a b)"));
+    }
+
+    #[test]
+    fn quasiquote() {
+        let r = eval_each("(define x 5)
+                           (define l '(a b))
+                           `(1 ,x ,@l (,@l) ,(+ x 1) x)
+                           '(1 5 a b (a b) 6 x)
+                           `,x
+                           `(,@'() . ,@l)
+                           '(. a b)");
+        assert_eq!(r[3], r[2]);
+        assert_eq!(Ok(Data::DExp(SExp::Num(Number::Int(5), None))), r[4]);
+        assert_eq!(r[6], r[5]);
+    }
+
+    #[test]
+    fn quasiquote_holds_any_value() {
+        let r = eval_each("(define f (lambda (x) x))
+                           `(,f)").pop().unwrap().unwrap();
+        if let Data::DList(ref l) = r {
+            assert!(matches!(l[0], Data::DFun(_)));
+        } else {
+            panic!("unexpected result {:?}", r);
+        }
+    }
+
+    #[test]
+    fn nested_quasiquote() {
+        let r = eval_each("`(a `(b ,(c ,(+ 1 2))))
+                           '(a (quasiquote (b (unquote (c 3)))))
+                           (define x 'y)
+                           `(1 `(2 ,(3 ,@'(x) ,x)))
+                           '(1 (quasiquote (2 (unquote (3 x y)))))
+                           `(1 `(2 ,,x))
+                           '(1 (quasiquote (2 (unquote y))))
+                           `(1 `(2 ,@,@'(x)))
+                           '(1 (quasiquote (2 (unquote-splicing x))))");
+        assert_eq!(r[1], r[0]);
+        assert_eq!(r[4], r[3]);
+        assert_eq!(r[6], r[5]);
+        assert_eq!(r[8], r[7]);
+    }
+
+    #[test]
+    fn quasiquote_errors() {
+        assert_eq!(ErrorKind::SyntaxError, eval_err(",a"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("(define a '(1)) `(,@,@a)"));
+        assert_eq!(ErrorKind::TypeError, eval_err("`(,@1)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("`,@'(1)"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("`,@,a"));
+        assert_eq!(ErrorKind::SyntaxError, eval_err("`(unquote 1 2)"));
     }
 }
@@ -0,0 +1,12 @@
+//! Bungling Mutant Lisp: a reader and tree-walking interpreter, which may be embedded in other
+//! programs as well as being run by the `bml` command
+
+pub mod parse;
+pub mod symtable;
+pub mod interpret;
+pub mod builtin;
+pub mod number;
+pub mod bigint;
+pub mod rational;
+pub mod print;
+pub mod native;
@@ -1,34 +1,59 @@
-mod parse;
-mod symtable;
-mod interpret;
-mod builtin;
+extern crate bml;
+extern crate rustyline;
+
+mod repl;
 
 use std::env;
-use std::io::prelude::*;
+use std::io::BufReader;
 use std::fs::File;
-use parse::Parser;
-use symtable::SymTable;
-use parse::SExp;
+use bml::parse::Parser;
+use bml::symtable::SymTable;
+use bml::parse::SExp;
+use bml::interpret::Data;
+use bml::interpret::EvalError;
+use bml::interpret::Interpreter;
+use bml::print::Render;
+
+fn report_eval_error(filename: &str, e: &EvalError) {
+    if e.span.is_some() {
+        println!("{}:{}", filename, e);
+    } else {
+        println!("{}: {}", filename, e);
+    }
+}
+
+/// Evaluates each top-level form in the file as soon as it has been read
+fn run_file(st: &SymTable, interpreter: &Interpreter, filename: &str) {
+    let f = match File::open(filename) {
+        Ok(f) => f,
+        Err(e) => {
+            println!("{}: {}", filename, e);
+            return;
+        }
+    };
+    let mut result = Data::DExp(SExp::Nil);
+    for datum in Parser::from_reader(st, BufReader::new(f)) {
+        match datum {
+            Ok(s) => {
+                match interpreter.eval_sexp(&s) {
+                    Ok(d) => result = d,
+                    Err(e) => return report_eval_error(filename, &e)
+                }
+            },
+            Err(e) => return println!("{}:{}", filename, e)
+        }
+    }
+    print!("end: {}", result.write(st));
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let mut f = File::open(&args[1]).unwrap();
-
-    let mut b = String::new();
-    f.read_to_string(&mut b).unwrap();
-    let i = b.chars().peekable();
     let st = SymTable::new();
-    let interpreter = interpret::Interpreter::new(&st);
-    builtin::init(&st, &interpreter);
-    let mut parser = Parser::new(st, i);
-    match parser.compilation_unit() {
-        Ok(SExp::List(l)) => {
-            print!("end: {:?}", interpreter.eval_expressions(&l));
-        },
-        Ok(s) => {
-            print!("end: {:?}", interpreter.eval(&interpret::Data::DExp(s)));
-        },
-        Err(e) => println!("parse failed: {}", e.msg)
+    let interpreter = Interpreter::new(&st);
+    bml::builtin::init(&st, &interpreter);
+    match args.get(1) {
+        Some(filename) => run_file(&st, &interpreter, filename),
+        None => repl::run(&st, &interpreter),
     }
 }
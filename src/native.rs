@@ -0,0 +1,404 @@
+//! Conversions between `Data` and Rust types, so that plain Rust functions can be registered with
+//! `Interpreter::register()`, which checks and converts their arguments
+
+use interpret::Data;
+use interpret::EvalError;
+use interpret::EvalResult;
+use interpret::ErrorKind;
+use number::Number;
+use parse::SExp;
+use symtable::Symbol;
+
+/// A type which a registered function may take as a param.  `Item` is the type actually passed,
+/// which differs from `Self` only in its lifetime, so that for example a `&str` param can borrow
+/// from the arguments of a call.
+pub trait FromData {
+    type Item<'a>;
+
+    /// For error messages, as in "expected a string"
+    const EXPECTED: &'static str;
+
+    /// The plural of `EXPECTED`, as in "expected a list of strings"
+    const EXPECTED_PLURAL: &'static str;
+
+    /// True if trailing params of this type may be left out of a call
+    const OPTIONAL: bool = false;
+
+    fn from_data(data: &Data) -> Option<Self::Item<'_>>;
+
+    /// What was expected of an argument which `from_data()` rejected.  Usually just `EXPECTED`,
+    /// but a list describes its items too.
+    fn expected() -> String {
+        Self::EXPECTED.to_string()
+    }
+
+    /// The value for a param which was left out of a call, if that is allowed
+    fn missing<'a>() -> Option<Self::Item<'a>> {
+        None
+    }
+}
+
+/// A type which a registered function may return
+pub trait IntoData {
+    fn into_data(self) -> EvalResult;
+}
+
+impl FromData for Data {
+    type Item<'a> = Data;
+    const EXPECTED: &'static str = "a value";
+    const EXPECTED_PLURAL: &'static str = "values";
+
+    fn from_data(data: &Data) -> Option<Data> {
+        Some(data.clone())
+    }
+}
+
+impl FromData for Number {
+    type Item<'a> = Number;
+    const EXPECTED: &'static str = "a number";
+    const EXPECTED_PLURAL: &'static str = "numbers";
+
+    fn from_data(data: &Data) -> Option<Number> {
+        match *data {
+            Data::DExp(SExp::Num(ref n, _)) => Some(n.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Integers too large for an `i64` are rejected, as are inexact numbers, even whole ones
+impl FromData for i64 {
+    type Item<'a> = i64;
+    const EXPECTED: &'static str = "an integer";
+    const EXPECTED_PLURAL: &'static str = "integers";
+
+    fn from_data(data: &Data) -> Option<i64> {
+        match *data {
+            Data::DExp(SExp::Num(Number::Int(i), _)) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+impl FromData for f64 {
+    type Item<'a> = f64;
+    const EXPECTED: &'static str = "a number";
+    const EXPECTED_PLURAL: &'static str = "numbers";
+
+    fn from_data(data: &Data) -> Option<f64> {
+        match *data {
+            Data::DExp(SExp::Num(ref n, _)) => Some(n.as_f64()),
+            _ => None,
+        }
+    }
+}
+
+impl FromData for bool {
+    type Item<'a> = bool;
+    const EXPECTED: &'static str = "a boolean";
+    const EXPECTED_PLURAL: &'static str = "booleans";
+
+    fn from_data(data: &Data) -> Option<bool> {
+        match *data {
+            Data::DExp(SExp::Boolean(b, _)) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl FromData for String {
+    type Item<'a> = String;
+    const EXPECTED: &'static str = "a string";
+    const EXPECTED_PLURAL: &'static str = "strings";
+
+    fn from_data(data: &Data) -> Option<String> {
+        <&str>::from_data(data).map(String::from)
+    }
+}
+
+impl FromData for &str {
+    type Item<'a> = &'a str;
+    const EXPECTED: &'static str = "a string";
+    const EXPECTED_PLURAL: &'static str = "strings";
+
+    fn from_data(data: &Data) -> Option<&str> {
+        match *data {
+            Data::DExp(SExp::LString(ref s, _)) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl FromData for Symbol {
+    type Item<'a> = Symbol;
+    const EXPECTED: &'static str = "a symbol";
+    const EXPECTED_PLURAL: &'static str = "symbols";
+
+    fn from_data(data: &Data) -> Option<Symbol> {
+        match *data {
+            Data::DExp(SExp::Sym(s, _)) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// A param which is `None` when left out of a call, or given as nil
+impl<T: FromData> FromData for Option<T> {
+    type Item<'a> = Option<T::Item<'a>>;
+    const EXPECTED: &'static str = T::EXPECTED;
+    const EXPECTED_PLURAL: &'static str = T::EXPECTED_PLURAL;
+    const OPTIONAL: bool = true;
+
+    fn from_data(data: &Data) -> Option<Option<T::Item<'_>>> {
+        match *data {
+            Data::DExp(SExp::Nil) => Some(None),
+            _ => T::from_data(data).map(Some),
+        }
+    }
+
+    fn expected() -> String {
+        T::expected()
+    }
+
+    fn missing<'a>() -> Option<Option<T::Item<'a>>> {
+        Some(None)
+    }
+}
+
+/// A list, every item of which must convert to `T`
+impl<T: FromData> FromData for Vec<T> {
+    type Item<'a> = Vec<T::Item<'a>>;
+    const EXPECTED: &'static str = "a list";
+    const EXPECTED_PLURAL: &'static str = "lists";
+
+    fn from_data(data: &Data) -> Option<Vec<T::Item<'_>>> {
+        match *data {
+            Data::DList(ref l) => l.iter().map(T::from_data).collect(),
+            Data::DExp(SExp::Nil) => Some(vec!()),
+            _ => None,
+        }
+    }
+
+    fn expected() -> String {
+        format!("a list of {}", T::EXPECTED_PLURAL)
+    }
+}
+
+impl IntoData for Data {
+    fn into_data(self) -> EvalResult {
+        Ok(self)
+    }
+}
+
+impl IntoData for () {
+    fn into_data(self) -> EvalResult {
+        Ok(Data::DExp(SExp::Nil))
+    }
+}
+
+impl IntoData for Number {
+    fn into_data(self) -> EvalResult {
+        Ok(Data::DExp(SExp::Num(self, None)))
+    }
+}
+
+impl IntoData for i64 {
+    fn into_data(self) -> EvalResult {
+        Number::Int(self).into_data()
+    }
+}
+
+impl IntoData for f64 {
+    fn into_data(self) -> EvalResult {
+        Number::Real(self).into_data()
+    }
+}
+
+impl IntoData for bool {
+    fn into_data(self) -> EvalResult {
+        Ok(Data::DExp(SExp::Boolean(self, None)))
+    }
+}
+
+impl IntoData for String {
+    fn into_data(self) -> EvalResult {
+        Ok(Data::DExp(SExp::LString(self, None)))
+    }
+}
+
+impl IntoData for &str {
+    fn into_data(self) -> EvalResult {
+        self.to_string().into_data()
+    }
+}
+
+impl IntoData for Symbol {
+    fn into_data(self) -> EvalResult {
+        Ok(Data::DExp(SExp::Sym(self, None)))
+    }
+}
+
+/// `None` gives nil
+impl<T: IntoData> IntoData for Option<T> {
+    fn into_data(self) -> EvalResult {
+        match self {
+            Some(v) => v.into_data(),
+            None => ().into_data(),
+        }
+    }
+}
+
+impl<T: IntoData> IntoData for Vec<T> {
+    fn into_data(self) -> EvalResult {
+        self.into_iter().map(T::into_data).collect::<Result<Vec<Data>, EvalError>>().map(Data::DList)
+    }
+}
+
+/// So that a registered function can fail
+impl<T: IntoData> IntoData for Result<T, EvalError> {
+    fn into_data(self) -> EvalResult {
+        self.and_then(T::into_data)
+    }
+}
+
+/// A Rust function which can be called with a list of arguments, implemented for functions of up
+/// to six params, each of which is `FromData`, returning something `IntoData`.  `Params` is the
+/// tuple of param types, which just serves to tell the implementations apart.
+pub trait NativeFn<Params>: 'static {
+    /// `name` is that of the function, for error messages
+    fn call(&self, name: &str, args: &[Data]) -> EvalResult;
+}
+
+/// Checks the number of arguments against the params, of which `optional` says which may be
+/// left out
+fn check_arity(name: &str, args: &[Data], optional: &[bool]) -> Result<(), EvalError> {
+    let max = optional.len();
+    let min = optional.iter().rposition(|o| !o ).map_or(0, |i| i + 1);
+    if args.len() < min || args.len() > max {
+        let expected = if min == max { format!("{}", min) } else { format!("{} to {}", min, max) };
+        Err(EvalError::new(ErrorKind::ArityError, format!("'{}' expected {} arguments, got {}", name, expected, args.len())))
+    } else {
+        Ok(())
+    }
+}
+
+/// Converts the argument for the param at `index`, which `check_arity()` has allowed for
+fn param<'a, T: FromData>(name: &str, args: &'a [Data], index: usize) -> Result<T::Item<'a>, EvalError> {
+    match args.get(index) {
+        Some(arg) => T::from_data(arg).ok_or_else(|| {
+            EvalError::with_value(ErrorKind::TypeError, arg.clone(), format!("invalid arguments for '{}', expected {}", name, T::expected()))
+        }),
+        None => Ok(T::missing().expect("argument count not checked")),
+    }
+}
+
+macro_rules! impl_native_fn {
+    ($($p:ident),*) => {
+        // `F` is required to take both the param types themselves, which lets them be inferred
+        // from the function, and their `Item` types for any lifetime, which is what gets passed
+        impl<F, R, $($p),*> NativeFn<($($p,)*)> for F
+            where F: 'static + Fn($($p),*) -> R + for<'a> Fn($($p::Item<'a>),*) -> R,
+                  R: IntoData,
+                  $($p: FromData),*
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn call(&self, name: &str, args: &[Data]) -> EvalResult {
+                // picks out the `Fn` implementation taking the `Item` types
+                fn call_with<R, $($p),*>(f: impl Fn($($p),*) -> R, $($p: $p),*) -> R {
+                    f($($p),*)
+                }
+                check_arity(name, args, &[$($p::OPTIONAL),*])?;
+                let mut index = 0..;
+                $(let $p = param::<$p>(name, args, index.next().unwrap())?;)*
+                call_with(self, $($p),*).into_data()
+            }
+        }
+    }
+}
+
+impl_native_fn!();
+impl_native_fn!(A);
+impl_native_fn!(A, B);
+impl_native_fn!(A, B, C);
+impl_native_fn!(A, B, C, D);
+impl_native_fn!(A, B, C, D, E);
+impl_native_fn!(A, B, C, D, E, G);
+
+#[cfg(test)]
+mod tests {
+    use interpret::Data;
+    use interpret::EvalError;
+    use interpret::EvalResult;
+    use interpret::ErrorKind;
+    use interpret::Interpreter;
+    use parse::Parser;
+    use print::Render;
+    use symtable::SymTable;
+
+    fn eval_each(interpreter: &Interpreter, st: &SymTable, text: &str) -> Vec<EvalResult> {
+        Parser::new(st, text.chars()).map(|e| interpreter.eval_sexp(&e.unwrap()) ).collect()
+    }
+
+    fn to_strings(st: &SymTable, results: Vec<EvalResult>) -> Vec<String> {
+        results.into_iter().map(|r| match r {
+            Ok(d) => d.write(st).to_string(),
+            Err(e) => format!("{:?}: {}", e.kind, e.msg),
+        }).collect()
+    }
+
+    #[test]
+    fn register() {
+        let st = SymTable::new();
+        let i = Interpreter::new(&st);
+        i.register("add", |a: i64, b: i64| a + b );
+        i.register("half", |a: f64| a / 2.0 );
+        i.register("shout", |s: &str| s.to_uppercase() );
+        i.register("first-word", |s: &str| s.split(' ').next().map(String::from) );
+        i.register("join", |words: Vec<String>, sep: Option<&str>| words.join(sep.unwrap_or(" ")) );
+        i.register("lengths", |words: Vec<&str>| words.iter().map(|w| w.len() as i64 ).collect::<Vec<i64>>() );
+        i.register("answer", || 42 );
+        i.register("ignore", |_: Data| () );
+        let results = eval_each(&i, &st, "(add 1 2)  (half 3)  (shout \"hi\")  (first-word \"\")  (first-word \"a b\")
+                                           (join '(\"a\" \"b\"))  (join '(\"a\" \"b\") \"-\")  (join '() \"-\")
+                                           (lengths '(\"ab\" \"c\"))  (answer)  (ignore 1)");
+        let expected = ["3", "1.5", "\"HI\"", "\"\"", "\"a\"", "\"a b\"", "\"a-b\"", "\"\"", "(2 1)", "42", "()"];
+        assert_eq!(expected.iter().map(|e| e.to_string() ).collect::<Vec<_>>(), to_strings(&st, results));
+    }
+
+    #[test]
+    fn argument_errors() {
+        let st = SymTable::new();
+        let i = Interpreter::new(&st);
+        i.register("add", |a: i64, b: i64| a + b );
+        i.register("join", |words: Vec<String>, sep: Option<&str>| words.join(sep.unwrap_or(" ")) );
+        i.register("sum", |ns: Vec<i64>| ns.iter().sum::<i64>() );
+        i.register("sum-all", |ns: Option<Vec<Vec<i64>>>| ns.map_or(0, |ns| ns.iter().flatten().sum::<i64>()) );
+        let results = eval_each(&i, &st, "(add 1)  (add 1 2 3)  (add 1 \"2\")  (add 1 2.0)  (join)  (join '(\"a\" 1))  (join '() 1)
+                                           (join \"a\")  (sum '(1 2.5 3))  (sum-all '((1) 2))  (sum-all '((1) (2)))");
+        let expected = [
+            "ArityError: 'add' expected 2 arguments, got 1",
+            "ArityError: 'add' expected 2 arguments, got 3",
+            "TypeError: invalid arguments for 'add', expected an integer",
+            "TypeError: invalid arguments for 'add', expected an integer",
+            "ArityError: 'join' expected 1 to 2 arguments, got 0",
+            "TypeError: invalid arguments for 'join', expected a list of strings",
+            "TypeError: invalid arguments for 'join', expected a string",
+            "TypeError: invalid arguments for 'join', expected a list of strings",
+            "TypeError: invalid arguments for 'sum', expected a list of integers",
+            "TypeError: invalid arguments for 'sum-all', expected a list of lists",
+            "3",
+        ];
+        assert_eq!(expected.iter().map(|e| e.to_string() ).collect::<Vec<_>>(), to_strings(&st, results));
+    }
+
+    #[test]
+    fn failing_function() {
+        let st = SymTable::new();
+        let i = Interpreter::new(&st);
+        i.register("checked-div", |a: i64, b: i64| {
+            a.checked_div(b).ok_or_else(|| EvalError::new(ErrorKind::DivideByZero, "division by zero".to_string()) )
+        });
+        let results = eval_each(&i, &st, "(checked-div 7 2)  (checked-div 1 0)");
+        assert_eq!(vec!("3", "DivideByZero: division by zero"), to_strings(&st, results));
+    }
+}
@@ -0,0 +1,360 @@
+use std::cmp::Ordering;
+use std::fmt;
+use bigint::BigInt;
+use rational::Rational;
+
+/// A numeric value.  `Int`, `Big` and `Ratio` values are exact, and arithmetic between them
+/// gives an exact result, never overflowing but moving from `Int` to `Big` as required.
+/// Arithmetic involving a `Real` promotes the other value to a `Real`.
+#[derive(Debug,Clone)]
+pub enum Number {
+    Int(i64),
+    /// Only used for values outside the range of `Int`
+    Big(BigInt),
+    /// Only used for values which are not integers
+    Ratio(Rational),
+    Real(f64),
+}
+
+// This is structural identity rather than numeric equality, so that `Number` can be `Eq`: an
+// exact value never equals a Real, and Reals are compared bit-for-bit (hence NaN equals itself).
+// Use `Number::compare()` for numeric comparison.
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => a == b,
+            (Number::Big(a), Number::Big(b)) => a == b,
+            (Number::Ratio(a), Number::Ratio(b)) => a == b,
+            (Number::Real(a), Number::Real(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+impl Eq for Number {
+}
+
+// Reals always include a '.' or exponent, so that they read back as Reals rather than integers
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::Int(i) => write!(f, "{}", i),
+            Number::Big(ref b) => write!(f, "{}", b),
+            Number::Ratio(ref r) => write!(f, "{}", r),
+            Number::Real(r) if r.is_nan() => write!(f, "+nan.0"),
+            Number::Real(r) if r.is_infinite() => write!(f, "{}inf.0", if r > 0.0 { "+" } else { "-" }),
+            Number::Real(r) => write!(f, "{:?}", r),
+        }
+    }
+}
+
+impl Number {
+    /// Gives an `Int` if the value is in range, so that each integer has just one representation
+    pub fn from_big(b: BigInt) -> Number {
+        match b.to_i64() {
+            Some(i) => Number::Int(i),
+            None => Number::Big(b),
+        }
+    }
+
+    /// Gives an integer if the denominator is 1
+    pub fn from_ratio(r: Rational) -> Number {
+        if r.is_integer() {
+            Number::from_big(r.numerator().clone())
+        } else {
+            Number::Ratio(r)
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Number::Int(i) => i as f64,
+            Number::Big(ref b) => b.to_f64(),
+            Number::Ratio(ref r) => r.to_f64(),
+            Number::Real(r) => r,
+        }
+    }
+
+    /// `None` for a Real
+    fn to_ratio(&self) -> Option<Rational> {
+        match *self {
+            Number::Int(i) => Some(Rational::from_int(BigInt::from_i64(i))),
+            Number::Big(ref b) => Some(Rational::from_int(b.clone())),
+            Number::Ratio(ref r) => Some(r.clone()),
+            Number::Real(_) => None,
+        }
+    }
+
+    /// Applies `int_op` if both values are `Int`, falling back to `exact_op` if that gives `None`
+    /// or if either is `Big` or `Ratio`.  Applies `real_op` to both values as `f64` if either is
+    /// a Real.
+    fn arith<I, E, R>(&self, other: &Number, int_op: I, exact_op: E, real_op: R) -> Number
+        where I: Fn(i64, i64) -> Option<i64>,
+              E: Fn(&Rational, &Rational) -> Rational,
+              R: Fn(f64, f64) -> f64
+    {
+        if let (Number::Int(a), Number::Int(b)) = (self, other) {
+            if let Some(result) = int_op(*a, *b) {
+                return Number::Int(result);
+            }
+        }
+        match (self.to_ratio(), other.to_ratio()) {
+            (Some(a), Some(b)) => Number::from_ratio(exact_op(&a, &b)),
+            _ => Number::Real(real_op(self.as_f64(), other.as_f64())),
+        }
+    }
+
+    pub fn add(&self, other: &Number) -> Number {
+        self.arith(other, i64::checked_add, Rational::add, |a, b| a + b)
+    }
+
+    pub fn sub(&self, other: &Number) -> Number {
+        self.arith(other, i64::checked_sub, Rational::sub, |a, b| a - b)
+    }
+
+    pub fn mul(&self, other: &Number) -> Number {
+        self.arith(other, i64::checked_mul, Rational::mul, |a, b| a * b)
+    }
+
+    /// Division of exact values is exact, so may give a `Ratio`.  `None` if an exact value is
+    /// divided by zero; real division by zero gives an infinity or NaN.
+    pub fn div(&self, other: &Number) -> Option<Number> {
+        if self.is_exact() && other.is_exact_zero() {
+            return None;
+        }
+        let int_div = |a: i64, b: i64| if a.checked_rem(b) == Some(0) { a.checked_div(b) } else { None };
+        Some(self.arith(other, int_div, |a, b| a.div(b).unwrap(), |a, b| a / b))
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(*self, Number::Real(_))
+    }
+
+    /// True for an exact zero, which cannot be divided by
+    pub fn is_exact_zero(&self) -> bool {
+        *self == Number::Int(0)
+    }
+
+    /// Numeric comparison, `None` if either value is NaN
+    pub fn compare(&self, other: &Number) -> Option<Ordering> {
+        if let (Number::Int(a), Number::Int(b)) = (self, other) {
+            return Some(a.cmp(b));
+        }
+        match (self.to_ratio(), other.to_ratio()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
+        }
+    }
+
+    /// `None` for a Real
+    pub fn numerator(&self) -> Option<Number> {
+        self.to_ratio().map(|r| Number::from_big(r.numerator().clone()) )
+    }
+
+    /// `None` for a Real
+    pub fn denominator(&self) -> Option<Number> {
+        self.to_ratio().map(|r| Number::from_big(r.denominator().clone()) )
+    }
+
+    pub fn to_inexact(&self) -> Number {
+        Number::Real(self.as_f64())
+    }
+
+    /// Applies the given rounding to a `Ratio` or `Real`; integers are returned unchanged
+    fn rounding<E, R>(&self, exact_op: E, real_op: R) -> Number
+        where E: Fn(&Rational) -> BigInt,
+              R: Fn(f64) -> f64
+    {
+        match *self {
+            Number::Ratio(ref r) => Number::from_big(exact_op(r)),
+            Number::Real(r) => Number::Real(real_op(r)),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn floor(&self) -> Number {
+        self.rounding(Rational::floor, f64::floor)
+    }
+
+    /// Rounds to even when half way between two integers
+    pub fn round(&self) -> Number {
+        self.rounding(Rational::round, f64::round_ties_even)
+    }
+
+    pub fn truncate(&self) -> Number {
+        self.rounding(Rational::truncate, f64::trunc)
+    }
+
+    /// Parses numeric literal syntax, `[+-]digits` for integers, `[+-]digits/digits` for
+    /// rationals and `[+-]digits.digits[e[+-]digits]` for reals (where either the integer or
+    /// fractional part may be omitted, but not both), plus `+inf.0`, `-inf.0` and `+nan.0`.
+    /// Gives `None` for text which does not follow this syntax, or a rational with a zero
+    /// denominator.
+    pub fn parse(text: &str) -> Option<Number> {
+        match text {
+            "+inf.0" => return Some(Number::Real(f64::INFINITY)),
+            "-inf.0" => return Some(Number::Real(f64::NEG_INFINITY)),
+            "+nan.0" | "-nan.0" => return Some(Number::Real(f64::NAN)),
+            _ => (),
+        }
+        if let Some(slash) = text.find('/') {
+            let den = &text[slash + 1..];
+            if !den.starts_with(|c:char| c.is_ascii_digit() ) {
+                return None;
+            }
+            let num = BigInt::parse(&text[..slash])?;
+            let den = BigInt::parse(den)?;
+            return Rational::new(num, den).map(Number::from_ratio);
+        }
+        let bytes = text.as_bytes();
+        let mut i = 0;
+        let digits = |i: &mut usize| {
+            let start = *i;
+            while *i < bytes.len() && bytes[*i].is_ascii_digit() {
+                *i += 1;
+            }
+            *i - start
+        };
+        if i < bytes.len() && (bytes[i] == b'+' || bytes[i] == b'-') {
+            i += 1;
+        }
+        let int_digits = digits(&mut i);
+        let mut frac_digits = 0;
+        let mut is_real = false;
+        if i < bytes.len() && bytes[i] == b'.' {
+            i += 1;
+            is_real = true;
+            frac_digits = digits(&mut i);
+        }
+        if int_digits == 0 && frac_digits == 0 {
+            return None;
+        }
+        if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+            i += 1;
+            is_real = true;
+            if i < bytes.len() && (bytes[i] == b'+' || bytes[i] == b'-') {
+                i += 1;
+            }
+            if digits(&mut i) == 0 {
+                return None;
+            }
+        }
+        if i != bytes.len() {
+            return None;
+        }
+        if is_real {
+            text.parse::<f64>().ok().map(Number::Real)
+        } else {
+            match text.parse::<i64>() {
+                Ok(i) => Some(Number::Int(i)),
+                Err(_) => BigInt::parse(text).map(Number::Big),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Number;
+    use std::cmp::Ordering;
+    use bigint::BigInt;
+
+    fn big(s: &str) -> Number {
+        Number::Big(BigInt::parse(s).unwrap())
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Some(Number::Int(-12)), Number::parse("-12"));
+        assert_eq!(Some(Number::Int(7)), Number::parse("+7"));
+        assert_eq!(Some(Number::Real(2.75)), Number::parse("2.75"));
+        assert_eq!(Some(Number::Real(1e-9)), Number::parse("1e-9"));
+        assert_eq!(Some(Number::Real(-0.5)), Number::parse("-.5"));
+        assert_eq!(Some(Number::Real(5.0)), Number::parse("5."));
+        assert_eq!(Some(Number::Real(2.5e10)), Number::parse("2.5E+10"));
+        assert_eq!(Some(Number::Int(i64::MIN)), Number::parse("-9223372036854775808"));
+        assert_eq!(Some(Number::Big(BigInt::parse("9223372036854775808").unwrap())), Number::parse("9223372036854775808"));
+        for not_num in &["-", "+", ".", "1e", "e5", "1.2.3", "12abc", "inf", "NaN", "-.e1"] {
+            assert_eq!(None, Number::parse(not_num), "parsing {:?}", not_num);
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!("-12", Number::Int(-12).to_string());
+        assert_eq!("5.0", Number::Real(5.0).to_string());
+        assert_eq!("1e-9", Number::Real(1e-9).to_string());
+        assert_eq!("-3/4", Number::parse("-6/8").unwrap().to_string());
+        for special in &["+inf.0", "-inf.0", "+nan.0"] {
+            assert_eq!(*special, Number::parse(special).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn promotion() {
+        assert_eq!(Number::Int(5), Number::Int(2).add(&Number::Int(3)));
+        assert_eq!(Number::Real(5.5), Number::Int(2).add(&Number::Real(3.5)));
+        assert_eq!(Some(Number::Real(1.5)), Number::Real(3.0).div(&Number::Int(2)));
+        assert_eq!(Some(Number::Int(-2)), Number::Int(6).div(&Number::Int(-3)));
+        assert_eq!(None, Number::Int(1).div(&Number::Int(0)));
+    }
+
+    #[test]
+    fn overflow_promotion() {
+        assert_eq!(big("9223372036854775808"), Number::Int(i64::MAX).add(&Number::Int(1)));
+        assert_eq!(big("-9223372036854775809"), Number::Int(i64::MIN).sub(&Number::Int(1)));
+        assert_eq!(big("85070591730234615847396907784232501249"), Number::Int(i64::MAX).mul(&Number::Int(i64::MAX)));
+        assert_eq!(Some(big("9223372036854775808")), Number::Int(i64::MIN).div(&Number::Int(-1)));
+        // and back again
+        assert_eq!(Number::Int(i64::MAX), big("9223372036854775808").sub(&Number::Int(1)));
+        assert_eq!(Some(Number::Int(2)), big("18446744073709551616").div(&big("9223372036854775808")));
+        assert_eq!(None, big("18446744073709551616").div(&Number::Int(0)));
+        assert_eq!(Number::Real(1.8446744073709552e19), big("18446744073709551616").mul(&Number::Real(1.0)));
+    }
+
+    fn ratio(n: i64, d: i64) -> Number {
+        Number::parse(&format!("{}/{}", n, d)).unwrap()
+    }
+
+    #[test]
+    fn exact_division() {
+        assert_eq!(Some(ratio(3, 2)), Number::Int(3).div(&Number::Int(2)));
+        assert_eq!(Some(ratio(-1, 3)), Number::Int(1).div(&Number::Int(-3)));
+        assert_eq!(Some(Number::Int(2)), ratio(2, 3).div(&ratio(1, 3)));
+        assert_eq!(Number::Int(1), ratio(1, 3).add(&ratio(2, 3)));
+        assert_eq!(Number::Real(0.75), ratio(1, 2).add(&Number::Real(0.25)));
+        assert_eq!(None, ratio(1, 2).div(&Number::Int(0)));
+        assert_eq!(Some(Ordering::Less), ratio(1, 3).compare(&ratio(1, 2)));
+        assert_eq!(Some(Ordering::Greater), ratio(1, 3).compare(&Number::Real(0.3)));
+    }
+
+    #[test]
+    fn rational_literals() {
+        assert_eq!(Some(Number::Int(2)), Number::parse("4/2"));
+        assert_eq!(Some(Number::Real(0.75)), Number::parse("3/4").map(|n| n.to_inexact() ));
+        assert_eq!(Some(Number::Int(-3)), Number::parse("-3/4").unwrap().numerator());
+        assert_eq!(Some(Number::Int(4)), Number::parse("-6/8").unwrap().denominator());
+        for not_num in &["1/0", "1/-2", "1/", "/2", "1/2/3", "1.5/2"] {
+            assert_eq!(None, Number::parse(not_num), "parsing {:?}", not_num);
+        }
+    }
+
+    #[test]
+    fn rounding() {
+        assert_eq!(Number::Int(-4), ratio(-7, 2).floor());
+        assert_eq!(Number::Int(-3), ratio(-7, 2).truncate());
+        assert_eq!(Number::Int(-4), ratio(-7, 2).round());
+        assert_eq!(Number::Real(2.0), Number::Real(2.5).round());
+        assert_eq!(Number::Real(-3.0), Number::Real(-2.5).floor());
+        assert_eq!(Number::Int(7), Number::Int(7).floor());
+    }
+
+    #[test]
+    fn compare() {
+        assert_eq!(Some(Ordering::Equal), Number::Int(1).compare(&Number::Real(1.0)));
+        assert_eq!(Some(Ordering::Less), Number::Real(0.5).compare(&Number::Int(1)));
+        assert_eq!(None, Number::Real(f64::NAN).compare(&Number::Int(1)));
+        assert_ne!(Number::Int(1), Number::Real(1.0));
+        assert_eq!(Some(Ordering::Less), Number::Int(i64::MAX).compare(&big("9223372036854775808")));
+        assert_eq!(Some(Ordering::Greater), Number::Int(i64::MIN).compare(&big("-9223372036854775809")));
+    }
+}
@@ -1,29 +1,195 @@
+use std::cell::Cell;
 use std::cell::RefCell;
-use std::str::Chars;
-use std::iter::Peekable;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::str;
 use symtable::SymTable;
-use symtable::SymbolRef;
+use symtable::Symbol;
+use number::Number;
 
-#[derive(Debug,Eq,PartialEq,Clone)]
+/// A location within the parser's input.  Every parsed expression carries two of these, so they
+/// are kept to `u32`s, which saturate on inputs of 4GB or more.
+#[derive(Debug,Eq,PartialEq,Clone,Copy)]
+pub struct Pos {
+    /// starting from 1
+    pub line: u32,
+    /// starting from 1, counted in chars rather than bytes
+    pub col: u32,
+    /// byte offset from the start of input
+    pub offset: u32,
+}
+
+impl Pos {
+    pub fn start() -> Pos {
+        Pos {
+            line: 1,
+            col: 1,
+            offset: 0,
+        }
+    }
+
+    fn advance(self, c: char) -> Pos {
+        if c == '\n' {
+            Pos { line: self.line.saturating_add(1), col: 1, offset: self.offset.saturating_add(1) }
+        } else {
+            Pos { line: self.line, col: self.col.saturating_add(1), offset: self.offset.saturating_add(c.len_utf8() as u32) }
+        }
+    }
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// The region of input from which an expression was parsed, `end` being exclusive
+#[derive(Debug,Eq,PartialEq,Clone,Copy)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.start.fmt(f)
+    }
+}
+
+/// Expressions carry the span of source text they were parsed from, if any, so that runtime
+/// errors can be pointed back at the source.  Values made while running a program have none.
+#[derive(Debug,Eq,Clone)]
 pub enum SExp {
-    Sym(SymbolRef),
-    LString(String),
-    List(Vec<SExp>),
-    Num(i32),
-    Boolean(bool),
+    Sym(Symbol, Option<Span>),
+    LString(String, Option<Span>),
+    List(Vec<SExp>, Option<Span>),
+    Num(Number, Option<Span>),
+    Boolean(bool, Option<Span>),
     Nil
 }
 
+// Where an expression came from has no bearing on its value, so spans are ignored here
+impl PartialEq for SExp {
+    fn eq(&self, other: &SExp) -> bool {
+        match (self, other) {
+            (SExp::Sym(a, _), SExp::Sym(b, _)) => a == b,
+            (SExp::LString(a, _), SExp::LString(b, _)) => a == b,
+            (SExp::List(a, _), SExp::List(b, _)) => a == b,
+            (SExp::Num(a, _), SExp::Num(b, _)) => a == b,
+            (SExp::Boolean(a, _), SExp::Boolean(b, _)) => a == b,
+            (SExp::Nil, SExp::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug,Eq,PartialEq,Clone,Copy)]
+pub enum ParseErrorKind {
+    /// The input is not valid syntax, however it might continue
+    Syntax,
+    /// The input ended part way through an expression, so would be valid given more input
+    Incomplete,
+    /// The input could not be read
+    Io,
+}
+
 #[derive(Debug)]
 pub struct ParseError {
-    pub msg:String
+    pub kind:ParseErrorKind,
+    pub msg:String,
+    pub span:Span,
 }
 
-type ParseResult<'a> = Result<SExp, ParseError>;
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.msg)
+    }
+}
 
+type ParseResult = Result<SExp, ParseError>;
+
+/// Decodes the UTF-8 text read from a `BufRead` into chars
+struct ReadChars<R> {
+    reader: R,
+}
+
+impl<R: BufRead> ReadChars<R> {
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            let b = match self.reader.fill_buf() {
+                Ok(buf) => buf.first().copied(),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if b.is_some() {
+                self.reader.consume(1);
+            }
+            return Ok(b);
+        }
+    }
+
+    fn char(&mut self, first: u8) -> io::Result<char> {
+        let len = match first {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 0,
+        };
+        let mut buf = [first, 0, 0, 0];
+        for b in buf.iter_mut().take(len).skip(1) {
+            match self.byte()? {
+                Some(next) => *b = next,
+                None => break,
+            }
+        }
+        match str::from_utf8(&buf[..len]) {
+            Ok(s) if len > 0 => Ok(s.chars().next().unwrap()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "input is not valid UTF-8")),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ReadChars<R> {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<io::Result<char>> {
+        match self.byte() {
+            Ok(Some(b)) => Some(self.char(b)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Reads expressions from a stream of chars.  Chars are only taken from the underlying input
+/// as they are needed, so a parser reading from a pipe or terminal can produce each top-level
+/// expression as soon as it is complete, by using the parser as an `Iterator`.
 pub struct Parser<'a> {
-    st:SymTable,
-    i:RefCell<Peekable<Chars<'a>>>
+    st:&'a SymTable,
+    i:RefCell<Box<dyn Iterator<Item=io::Result<char>> + 'a>>,
+    /// chars taken from `i` but not yet consumed
+    ahead:RefCell<VecDeque<char>>,
+    /// set if reading from `i` failed, in which case the parser sees the failure as end of input
+    io_error:RefCell<Option<io::Error>>,
+    /// set once the parser has produced an error when used as an `Iterator`
+    failed:bool,
+    pos:Cell<Pos>,
+}
+
+/// Chars which may appear in a symbol or number.  A symbol may not start with a digit (even
+/// after a sign or '.'), so that it can't be mistaken for a number.
+pub fn is_atom_char(c:char) -> bool {
+    c.is_alphanumeric() || "+-*/<>=!?_.:&%".contains(c)
+}
+
+/// True for text which can only have been intended as a number
+fn looks_numeric(s:&str) -> bool {
+    let s = s.strip_prefix(|c| c == '+' || c == '-').unwrap_or(s);
+    let s = s.strip_prefix('.').unwrap_or(s);
+    s.starts_with(|c:char| c.is_ascii_digit() )
 }
 
 fn esc(c:char) -> String {
@@ -37,22 +203,73 @@ fn esc(c:char) -> String {
 
 impl<'a> Parser<'a> {
 
-    pub fn new(st:SymTable, i:Peekable<Chars<'a>>) -> Parser<'a> {
+    pub fn new<I: Iterator<Item=char> + 'a>(st:&'a SymTable, i:I) -> Parser<'a> {
+        Parser::with_source(st, Box::new(i.map(Ok)))
+    }
+
+    /// A parser for the UTF-8 text read from the given reader
+    pub fn from_reader<R: BufRead + 'a>(st:&'a SymTable, reader:R) -> Parser<'a> {
+        Parser::with_source(st, Box::new(ReadChars { reader }))
+    }
+
+    fn with_source(st:&'a SymTable, i:Box<dyn Iterator<Item=io::Result<char>> + 'a>) -> Parser<'a> {
         Parser{
-            st: st,
-            i: RefCell::new(i)
+            st,
+            i: RefCell::new(i),
+            ahead: RefCell::new(VecDeque::new()),
+            io_error: RefCell::new(None),
+            failed: false,
+            pos: Cell::new(Pos::start()),
         }
     }
 
+    /// The position of the next char to be consumed
+    pub fn pos(&self) -> Pos {
+        self.pos.get()
+    }
+
+    fn err<T>(&self, start:Pos, msg:String) -> Result<T, ParseError> {
+        Err(ParseError{kind:ParseErrorKind::Syntax, msg, span:Span{start, end:self.pos()}})
+    }
+
+    /// An error for input which ends before the expression starting at `start` is complete
+    fn incomplete<T>(&self, start:Pos, msg:String) -> Result<T, ParseError> {
+        Err(ParseError{kind:ParseErrorKind::Incomplete, msg, span:Span{start, end:self.pos()}})
+    }
+
+    /// The char `n` places ahead of the next one to be consumed, reading more input if needed
+    fn lookahead(&self, n:usize) -> Option<char> {
+        let mut ahead = self.ahead.borrow_mut();
+        while ahead.len() <= n && self.io_error.borrow().is_none() {
+            match self.i.borrow_mut().next() {
+                Some(Ok(c)) => ahead.push_back(c),
+                Some(Err(e)) => *self.io_error.borrow_mut() = Some(e),
+                None => break,
+            }
+        }
+        ahead.get(n).copied()
+    }
+
     fn peek(&self) -> Option<char> {
-            self.i.borrow_mut().peek().map(|c| *c)
+        self.lookahead(0)
+    }
+
+    /// The char after the one `peek()` would give
+    fn peek_second(&self) -> Option<char> {
+        self.lookahead(1)
     }
 
-    fn next(&self) -> Option<char> {
-            self.i.borrow_mut().next()
+    fn next_char(&self) -> Option<char> {
+            self.lookahead(0);
+            let c = self.ahead.borrow_mut().pop_front();
+            if let Some(c) = c {
+                self.pos.set(self.pos.get().advance(c));
+            }
+            c
     }
 
-    fn skip_ws(&self) {
+    /// Skips whitespace, and also comments, which are allowed anywhere whitespace is
+    fn skip_ws(&self) -> Result<(), ParseError> {
         loop {
             match self.peek() {
                 None => break,
@@ -60,16 +277,29 @@ impl<'a> Parser<'a> {
                 Some('\n') => (),
                 Some('\r') => (),
                 Some('\t') => (),
+                Some(';') => {
+                    self.skip_comment();
+                    continue;
+                },
+                Some('#') => {
+                    match self.peek_second() {
+                        Some('|') => self.skip_block_comment()?,
+                        Some(';') => self.skip_datum_comment()?,
+                        _ => break
+                    }
+                    continue;
+                },
                 Some(_) => break
             }
-            let c = self.next().unwrap();
+            self.next_char();
         }
+        Ok(())
     }
 
     fn skip_comment(&self) {
         if self.peek_matches(';') {
             loop {
-                match self.next() {
+                match self.next_char() {
                     None => break,
                     Some('\n') => break,
                     Some(_) => ()
@@ -78,154 +308,260 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn expect(&self, e:char) {
-        let c = self.next().unwrap();
-        if c != e {  // TODO None
-            panic!("expected '{}', got '{}'", esc(e), esc(c));
+    /// Skips a `#| ... |#` comment, which may contain further nested block comments
+    fn skip_block_comment(&self) -> Result<(), ParseError> {
+        let start = self.pos();
+        self.expect('#')?;
+        self.expect('|')?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next_char() {
+                None => return self.incomplete(start, "end of input within block comment".to_string()),
+                Some('|') if self.peek_matches('#') => {
+                    self.next_char();
+                    depth -= 1;
+                },
+                Some('#') if self.peek_matches('|') => {
+                    self.next_char();
+                    depth += 1;
+                },
+                Some(_) => ()
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips `#;` and the datum following it
+    fn skip_datum_comment(&self) -> Result<(), ParseError> {
+        self.expect('#')?;
+        self.expect(';')?;
+        self.skip_ws()?;
+        self.sexp().map(|_| () )
+    }
+
+    fn expect(&self, e:char) -> Result<(), ParseError> {
+        let start = self.pos();
+        match self.next_char() {
+            Some(c) if c == e => Ok(()),
+            Some(c) => self.err(start, format!("expected '{}', got '{}'", esc(e), esc(c))),
+            None => self.incomplete(start, format!("expected '{}', got end of input", esc(e))),
         }
     }
 
     fn peek_matches(&self, e:char) -> bool {
-        self.peek().map_or(false, |v| v == e)
+        self.peek() == Some(e)
     }
 
-    fn sym(&self) -> ParseResult {
+    /// Reads a number or symbol.  The text up to the next delimiter is collected first, since
+    /// for example '-' alone is a symbol, but '-1' and '-.5e3' are numbers.
+    fn atom(&self) -> ParseResult {
+        let start = self.pos();
         let mut s = String::new();
         loop {
             match self.peek() {
-                None => break,
-                Some(c @ 'a' ... 'z') => s.push(c),
-                Some(_) => break
+                Some(c) if is_atom_char(c) => s.push(c),
+                _ => break
+            }
+            self.next_char();
+        }
+        match Number::parse(&s) {
+            Some(n) => Ok(SExp::Num(n, Some(Span{start, end:self.pos()}))),
+            None => {
+                if looks_numeric(&s) {
+                    self.err(start, format!("invalid numeric constant '{}'", s))
+                } else {
+                    Ok(SExp::Sym(self.st.sym_for(&s), Some(Span{start, end:self.pos()})))
+                }
             }
-            self.next();
         }
-        Ok(SExp::Sym(self.st.sym_for(&s)))
     }
 
     fn string(&self) -> ParseResult {
-        self.expect('"');
+        let start = self.pos();
+        self.expect('"')?;
         let mut s = String::new();
         loop {
-            let chr:char;
-            match self.peek() {
-                None => break,
-                Some(c) => chr = c
-            }
+            let chr = match self.peek() {
+                None => return self.incomplete(start, "end of input within string literal".to_string()),
+                Some(c) => c
+            };
             match chr {
                 '\\' => {
-                    self.expect('\\');
+                    let esc_start = self.pos();
+                    self.expect('\\')?;
                     match self.peek() {
                         Some('n') => s.push('\n'),
                         Some('t') => s.push('\t'),
                         Some('r') => s.push('\r'),
                         Some('"') => s.push('"'),
                         Some('\\') => s.push('\\'),
-                        Some(_) => return Err(ParseError{msg:"invalud escape sequence".to_string()}),
-                        None => return Err(ParseError{msg:"end of input within string literal".to_string()})
+                        Some(_) => {
+                            self.next_char();
+                            return self.err(esc_start, "invalid escape sequence".to_string())
+                        },
+                        None => return self.incomplete(start, "end of input within string literal".to_string())
                     }
                 },
                 '"' => break,
                 _ => s.push(chr)
             }
-            self.next();
+            self.next_char();
         }
-        self.expect('"');
-        Ok(SExp::LString(s))
-    }
-
-    fn num(&self) -> ParseResult {
-        let mut val:i32 = 0;
-        loop {
-            match self.peek() {
-                None => break,
-                Some(c @ '0' ... '9') => {
-                    if let Some(mul) = val.checked_mul(10) {
-                        if let Some(add) = mul.checked_add(c.to_digit(10).unwrap() as i32) {
-                            val = add;
-                        } else {
-                            return Err(ParseError{msg:"numeric constant too large".to_string()});
-                        }
-                    } else {
-                        return Err(ParseError{msg:"numeric constant too large".to_string()});
-                    }
-                },
-                Some(_) => break
-            }
-            self.next();
-        }
-        Ok(SExp::Num(val))
+        self.expect('"')?;
+        Ok(SExp::LString(s, Some(Span{start, end:self.pos()})))
     }
 
     pub fn sexp(&self) -> ParseResult {
-        let chr:char;
-        match self.peek() {
-            None => return Err(ParseError{msg:"end of input while expecting an ATOM".to_string()}),
-            Some(c) => chr = c
-        }
+        let start = self.pos();
+        let chr = match self.peek() {
+            None => return self.incomplete(start, "end of input while expecting an ATOM".to_string()),
+            Some(c) => c
+        };
         match chr {
             '('         => self.list(),
             '"'         => self.string(),
-            'a'...'z'   => self.sym(),
-            '0'...'9'   => self.num(),  // negative numeric constants just not possible ATM
-            '#'         => self.boolean(),
-            chr         => Err(ParseError{msg:format!("expected LIST, STRING or SYMBOL, but found '{}'", chr)})
+            '#'         => self.hash(),
+            '\''        => self.abbreviation("quote"),
+            '`'         => self.abbreviation("quasiquote"),
+            ','         => self.abbreviation("unquote"),
+            c if is_atom_char(c) => self.atom(),
+            chr         => {
+                self.next_char();
+                self.err(start, format!("expected LIST, STRING or SYMBOL, but found '{}'", esc(chr)))
+            }
         }
     }
 
-    fn boolean(&self) -> ParseResult {
-        self.next();  // discard '#'
-        match self.next() {
-            None => Err(ParseError{msg:"end of input within boolean literal".to_string()}),
-            Some('t') => Ok(SExp::Boolean(true)),
-            Some('f') => Ok(SExp::Boolean(false)),
-            Some(c) => Err(ParseError{msg:format!("invalid constant '#{}'", esc(c))}),
+    /// Expands reader shorthand like `'x` into `(quote x)`.  Given "unquote", also handles the
+    /// `,@x` form of "unquote-splicing".
+    fn abbreviation(&self, name:&str) -> ParseResult {
+        let start = self.pos();
+        self.next_char();  // discard the prefix char
+        let name = if name == "unquote" && self.peek_matches('@') {
+            self.next_char();
+            "unquote-splicing"
+        } else {
+            name
+        };
+        let prefix = SExp::Sym(self.st.sym_for(name), Some(Span{start, end:self.pos()}));
+        self.skip_ws()?;
+        let datum = self.sexp()?;
+        Ok(SExp::List(vec!(prefix, datum), Some(Span{start, end:self.pos()})))
+    }
+
+    /// Reads the booleans `#t` and `#f`, or a `#!name` token such as the `#!optional` marker in
+    /// a lambda param list.  The latter is read as a symbol, including the `#!`.
+    fn hash(&self) -> ParseResult {
+        let start = self.pos();
+        self.next_char();  // discard '#'
+        match self.next_char() {
+            None => self.incomplete(start, "end of input within boolean literal".to_string()),
+            Some('t') => Ok(SExp::Boolean(true, Some(Span{start, end:self.pos()}))),
+            Some('f') => Ok(SExp::Boolean(false, Some(Span{start, end:self.pos()}))),
+            Some('!') => {
+                let mut name = "#!".to_string();
+                while let Some(c) = self.peek().filter(|c| is_atom_char(*c) ) {
+                    name.push(c);
+                    self.next_char();
+                }
+                if name.len() > 2 {
+                    Ok(SExp::Sym(self.st.sym_for(&name), Some(Span{start, end:self.pos()})))
+                } else if self.peek().is_none() {
+                    self.incomplete(start, "end of input within '#!' token".to_string())
+                } else {
+                    self.err(start, "expected a name after '#!'".to_string())
+                }
+            },
+            Some(c) => self.err(start, format!("invalid constant '#{}'", esc(c))),
         }
     }
 
     fn list(&self) -> ParseResult {
-        self.expect('(');
-        self.skip_ws();
+        let start = self.pos();
+        self.expect('(')?;
+        self.skip_ws()?;
         let mut v:Vec<SExp> = Vec::new();
         while !self.peek_matches(')') {
-            v.push(try!(self.sexp()));
-            self.skip_ws();
+            if self.peek().is_none() {
+                return self.incomplete(start, "end of input within list".to_string());
+            }
+            v.push(self.sexp()?);
+            self.skip_ws()?;
         }
-        self.expect(')');
-        Ok(SExp::List(v))
+        self.expect(')')?;
+        Ok(SExp::List(v, Some(Span{start, end:self.pos()})))
     }
 
-    pub fn compilation_unit(&mut self) -> ParseResult {
-        let mut v:Vec<SExp> = Vec::new();
-        loop {
-            self.skip_ws();
+    /// Reads the next top-level expression, giving `None` at the end of input
+    pub fn next_datum(&self) -> Result<Option<SExp>, ParseError> {
+        let result = self.skip_ws().and_then(|_| {
             match self.peek() {
-                Some(_) => v.push(try!(self.sexp())),
-                None => break,
+                Some(_) => self.sexp().map(Some),
+                None => Ok(None),
             }
+        });
+        // whatever the parser made of the input, it will have been cut short by the failure
+        match self.io_error.borrow_mut().take() {
+            Some(e) => Err(ParseError{kind:ParseErrorKind::Io, msg:e.to_string(), span:Span{start:self.pos(), end:self.pos()}}),
+            None => result,
+        }
+    }
+
+    /// Reads all the remaining input, giving a list of the top-level expressions
+    pub fn compilation_unit(&mut self) -> ParseResult {
+        let start = self.pos();
+        let mut v:Vec<SExp> = Vec::new();
+        while let Some(datum) = self.next_datum()? {
+            v.push(datum);
         }
-        Ok(SExp::List(v))
+        Ok(SExp::List(v, Some(Span{start, end:self.pos()})))
     }
 
 }
 
+/// Gives each top-level expression in turn, ending after the first error
+impl<'a> Iterator for Parser<'a> {
+    type Item = ParseResult;
+
+    fn next(&mut self) -> Option<ParseResult> {
+        if self.failed {
+            return None;
+        }
+        match self.next_datum() {
+            Ok(datum) => datum.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Parser;
     use super::SExp;
     use super::ParseResult;
+    use super::Pos;
+    use super::Span;
+    use super::ParseErrorKind;
     use symtable::SymTable;
+    use number::Number;
+    use std::io;
+    use std::mem;
+    use std::io::prelude::*;
 
     fn parse_sexp(text: &str) -> ParseResult {
         let st = SymTable::new();
-        let mut p = Parser::new(st, text.chars().peekable());
+        let p = Parser::new(&st, text.chars().peekable());
         p.sexp()
     }
 
     #[test]
     fn num() {
         let r = parse_sexp("1234").unwrap();
-        if let SExp::Num(v) = r {
-            assert_eq!(1234, v);
+        if let SExp::Num(v, _) = r {
+            assert_eq!(Number::Int(1234), v);
         } else {
             panic!("Expected SExp::Num, got {:?}", r);
         }
@@ -233,10 +569,229 @@ mod tests {
 
     #[test]
     fn overflow_num() {
-        let r = parse_sexp("4294967296");
-        if r.is_ok() {
-            panic!("Expected failure parsing number larger than i32, got {:?}", r);
+        let r = parse_sexp("4294967296").unwrap();
+        assert_eq!(SExp::Num(Number::Int(4294967296), None), r);
+        let r = parse_sexp("-123456789012345678901234567890").unwrap();
+        if let SExp::Num(Number::Big(ref b), _) = r {
+            assert_eq!("-123456789012345678901234567890", b.to_string());
+        } else {
+            panic!("Expected SExp::Num(Number::Big), got {:?}", r);
+        }
+    }
+
+    #[test]
+    fn signed_num() {
+        assert_eq!(SExp::Num(Number::Int(-12), None), parse_sexp("-12").unwrap());
+        assert_eq!(SExp::Num(Number::Int(3), None), parse_sexp("+3").unwrap());
+        assert_eq!(SExp::Num(Number::Int(i64::MIN), None), parse_sexp("-9223372036854775808").unwrap());
+    }
+
+    #[test]
+    fn real_num() {
+        assert_eq!(SExp::Num(Number::Real(2.75), None), parse_sexp("2.75").unwrap());
+        assert_eq!(SExp::Num(Number::Real(1e-9), None), parse_sexp("1e-9").unwrap());
+        assert_eq!(SExp::Num(Number::Real(-0.5), None), parse_sexp("-.5").unwrap());
+    }
+
+    #[test]
+    fn invalid_num() {
+        assert!(parse_sexp("12abc").is_err());
+        assert!(parse_sexp("-1.2.3").is_err());
+        assert!(parse_sexp("1e").is_err());
+    }
+
+    #[test]
+    fn symbols() {
+        for name in &["string->number", "null?", "set!", "+", "-", "<=", "my-var2", "CamelCase", "a.b", "&rest", "%x", ":key", "λ", "-x", "+inf", "..."] {
+            let st = SymTable::new();
+            let expected = st.sym_for(name);
+            let p = Parser::new(&st, name.chars().peekable());
+            assert_eq!(SExp::Sym(expected, None), p.sexp().unwrap(), "parsing {:?}", name);
+        }
+    }
+
+    #[test]
+    fn hash_names() {
+        let st = SymTable::new();
+        let p = Parser::new(&st, "(a #!optional b)".chars().peekable());
+        let expected = vec!(SExp::Sym(st.sym_for("a"), None), SExp::Sym(st.sym_for("#!optional"), None), SExp::Sym(st.sym_for("b"), None));
+        assert_eq!(SExp::List(expected, None), p.sexp().unwrap());
+        assert_eq!(SExp::Sym(st.sym_for("#!rest"), None), Parser::new(&st, "#!rest".chars()).sexp().unwrap());
+        assert!(parse_sexp("#! x").is_err());
+    }
+
+    #[test]
+    fn sign_in_list() {
+        let st = SymTable::new();
+        let minus = st.sym_for("-");
+        let p = Parser::new(&st, "(- -1 1)".chars().peekable());
+        assert_eq!(SExp::List(vec!(SExp::Sym(minus, None), SExp::Num(Number::Int(-1), None), SExp::Num(Number::Int(1), None)), None), p.sexp().unwrap());
+    }
+
+    #[test]
+    fn abbreviations() {
+        for &(text, name) in &[("'a", "quote"), ("`a", "quasiquote"), (",a", "unquote"), (",@a", "unquote-splicing"), ("' a", "quote")] {
+            let st = SymTable::new();
+            let expected = SExp::List(vec!(SExp::Sym(st.sym_for(name), None), SExp::Sym(st.sym_for("a"), None)), None);
+            let p = Parser::new(&st, text.chars().peekable());
+            assert_eq!(expected, p.sexp().unwrap(), "parsing {:?}", text);
+        }
+        let st = SymTable::new();
+        let quote = st.sym_for("quote");
+        let p = Parser::new(&st, "'(1 '())".chars().peekable());
+        let expected = SExp::List(vec!(
+            SExp::Sym(quote, None),
+            SExp::List(vec!(
+                SExp::Num(Number::Int(1), None),
+                SExp::List(vec!(SExp::Sym(quote, None), SExp::List(vec!(), None)), None),
+            ), None),
+        ), None);
+        assert_eq!(expected, p.sexp().unwrap());
+        assert!(parse_sexp("'").is_err());
+    }
+
+    fn parse_unit(text: &str) -> ParseResult {
+        let st = SymTable::new();
+        let mut p = Parser::new(&st, text.chars().peekable());
+        p.compilation_unit()
+    }
+
+    fn nums(vals: &[i64]) -> SExp {
+        SExp::List(vals.iter().map(|v| SExp::Num(Number::Int(*v), None) ).collect(), None)
+    }
+
+    #[test]
+    fn line_comments() {
+        assert_eq!(nums(&[1, 2]), parse_unit("; leading\n1 ; after 1\n  ;; own line\n2 ; at end of input").unwrap());
+        let r = parse_unit("(1 ; inside\n 2 ;(3\n)").unwrap();
+        assert_eq!(SExp::List(vec!(nums(&[1, 2])), None), r);
+        assert_eq!(nums(&[]), parse_unit(";").unwrap());
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_eq!(nums(&[1, 2]), parse_unit("#| a |#1 #| outer #| inner |# still outer |# 2").unwrap());
+        assert_eq!(SExp::List(vec!(nums(&[1, 2])), None), parse_unit("(1#|x|#2 #||#)").unwrap());
+        assert_eq!(nums(&[1, 2]), parse_unit("1 #| ; not a line comment |# 2").unwrap());
+        let e = parse_unit("1 #| #| |#").unwrap_err();
+        assert_eq!(Pos{line:1, col:3, offset:2}, e.span.start);
+    }
+
+    #[test]
+    fn datum_comments() {
+        assert_eq!(nums(&[1, 3]), parse_unit("1 #;2 3").unwrap());
+        assert_eq!(nums(&[1, 3]), parse_unit("1 #; (2 (x y) \")\") 3").unwrap());
+        assert_eq!(SExp::List(vec!(nums(&[1])), None), parse_unit("(1 #;#;2 3)").unwrap());
+        assert_eq!(nums(&[]), parse_unit("#; ; comment\n 'x").unwrap());
+        assert!(parse_unit("(1 #;)").is_err());
+        assert!(parse_unit("#;").is_err());
+    }
+
+    #[test]
+    fn error_position() {
+        let e = parse_sexp("(foo\n  \"a\\qb\")").unwrap_err();
+        assert_eq!(Pos{line:2, col:5, offset:9}, e.span.start);
+        assert_eq!("2:5: invalid escape sequence", e.to_string());
+    }
+
+    #[test]
+    fn unterminated_list() {
+        let e = parse_sexp("(foo\n (bar)").unwrap_err();
+        assert_eq!(Pos{line:1, col:1, offset:0}, e.span.start);
+        assert_eq!(Pos{line:2, col:7, offset:11}, e.span.end);
+    }
+
+    #[test]
+    fn incomplete_input() {
+        for text in &["(foo", "(a (b)", "\"abc", "\"a\\", "'", "#", "#!", "#| a #| b |# c", "#;"] {
+            let e = parse_unit(text).unwrap_err();
+            assert_eq!(ParseErrorKind::Incomplete, e.kind, "for {:?}", text);
+        }
+        for text in &[")", "(a ]", "12a", "\"\\q", "#x", "(#!)"] {
+            let e = parse_unit(text).unwrap_err();
+            assert_eq!(ParseErrorKind::Syntax, e.kind, "for {:?}", text);
+        }
+    }
+
+    /// Gives some text, then fails
+    struct FailingReader<'a>(&'a [u8]);
+
+    impl<'a> Read for FailingReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "connection lost"));
+            }
+            self.0.read(buf)
         }
     }
 
+    #[test]
+    fn datums_from_reader() {
+        let st = SymTable::new();
+        let input = "(a \"\u{e9}\u{1f600}\") ; comment\n 2 (b".as_bytes();
+        let mut p = Parser::from_reader(&st, input);
+        let a = SExp::Sym(st.sym_for("a"), None);
+        let s = SExp::LString("\u{e9}\u{1f600}".to_string(), None);
+        assert_eq!(SExp::List(vec!(a, s), None), p.next().unwrap().unwrap());
+        assert_eq!(SExp::Num(Number::Int(2), None), p.next().unwrap().unwrap());
+        let e = p.next().unwrap().unwrap_err();
+        assert_eq!(ParseErrorKind::Incomplete, e.kind);
+        assert_eq!(Pos{line:2, col:4, offset:26}, e.span.start);
+        assert!(p.next().is_none());
+    }
+
+    #[test]
+    fn reader_errors() {
+        let st = SymTable::new();
+        let mut p = Parser::from_reader(&st, io::BufReader::new(FailingReader(b"(a b) (c")));
+        assert!(p.next().unwrap().is_ok());
+        let e = p.next().unwrap().unwrap_err();
+        assert_eq!(ParseErrorKind::Io, e.kind);
+        assert!(p.next().is_none());
+
+        let mut p = Parser::from_reader(&st, &b"(a \xff)"[..]);
+        assert_eq!(ParseErrorKind::Io, p.next().unwrap().unwrap_err().kind);
+        let mut p = Parser::from_reader(&st, &b"\"\xe2\x82"[..]);
+        assert_eq!(ParseErrorKind::Io, p.next().unwrap().unwrap_err().kind);
+    }
+
+    #[test]
+    fn list_span() {
+        let r = parse_sexp("(a\n (b c))").unwrap();
+        if let SExp::List(ref l, Some(span)) = r {
+            assert_eq!(Span{start:Pos{line:1, col:1, offset:0}, end:Pos{line:2, col:8, offset:10}}, span);
+            if let SExp::List(_, Some(inner)) = l[1] {
+                assert_eq!(Span{start:Pos{line:2, col:2, offset:4}, end:Pos{line:2, col:7, offset:9}}, inner);
+            } else {
+                panic!("Expected inner list, got {:?}", l[1]);
+            }
+        } else {
+            panic!("Expected SExp::List with span, got {:?}", r);
+        }
+    }
+
+    #[test]
+    fn atom_spans() {
+        let r = parse_sexp("(a 12\n \"s\" #t 'b)").unwrap();
+        let spans: Vec<Option<(u32, u32, u32)>> = match r {
+            SExp::List(ref l, _) => l.iter().map(|e| match *e {
+                SExp::Sym(_, span) | SExp::Num(_, span) | SExp::LString(_, span) | SExp::Boolean(_, span) | SExp::List(_, span) => span,
+                SExp::Nil => None,
+            }.map(|s| (s.start.line, s.start.col, s.end.col)) ).collect(),
+            _ => panic!("Expected SExp::List, got {:?}", r),
+        };
+        assert_eq!(vec!(Some((1, 2, 3)), Some((1, 4, 6)), Some((2, 2, 5)), Some((2, 6, 8)), Some((2, 9, 11))), spans);
+    }
+
+    #[test]
+    fn span_size() {
+        // every expression carries an optional span, so it needs to stay small
+        assert_eq!(24, mem::size_of::<Span>());
+    }
+
+    #[test]
+    fn multibyte_offsets() {
+        let e = parse_sexp("(\"\u{e9}\" [)").unwrap_err();
+        assert_eq!(Pos{line:1, col:6, offset:6}, e.span.start);
+    }
 }
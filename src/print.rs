@@ -0,0 +1,228 @@
+//! Rendering expressions and values as text.  Symbol names are kept by the `SymTable` which
+//! created them, so rendering anything which may contain a symbol needs the table.
+
+use std::fmt;
+use interpret::Data;
+use interpret::Fun;
+use parse::SExp;
+use symtable::Symbol;
+use symtable::SymTable;
+
+/// The two ways of rendering a value as text
+#[derive(Debug,Eq,PartialEq,Clone,Copy)]
+pub enum Style {
+    /// As the syntax which `Parser` reads, so that (functions and uninterned symbols aside) the
+    /// text reads back as an equal value
+    Write,
+    /// For people to read, with strings shown as their plain contents
+    Display,
+}
+
+pub trait Render {
+    fn render(&self, st: &SymTable, f: &mut fmt::Formatter, style: Style) -> fmt::Result;
+
+    /// Something to format in `Style::Write`, as in `println!("{}", x.write(&st))`
+    fn write<'a>(&'a self, st: &'a SymTable) -> Rendered<'a, Self> where Self: Sized {
+        Rendered { value: self, st, style: Style::Write }
+    }
+
+    /// Something to format in `Style::Display`, as in `println!("{}", x.display(&st))`
+    fn display<'a>(&'a self, st: &'a SymTable) -> Rendered<'a, Self> where Self: Sized {
+        Rendered { value: self, st, style: Style::Display }
+    }
+}
+
+/// See `Render::write()` and `Render::display()`
+pub struct Rendered<'a, T: 'a> {
+    value: &'a T,
+    st: &'a SymTable,
+    style: Style,
+}
+
+impl<'a, T: Render> fmt::Display for Rendered<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.render(self.st, f, self.style)
+    }
+}
+
+/// Writes a string literal, escaping the chars which `Parser::string()` requires to be escaped
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+fn render_list<T: Render>(st: &SymTable, f: &mut fmt::Formatter, items: &[T], style: Style) -> fmt::Result {
+    f.write_str("(")?;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(" ")?;
+        }
+        item.render(st, f, style)?;
+    }
+    f.write_str(")")
+}
+
+/// Uninterned symbols are shown with a `#:` prefix, so that they can be told apart from the
+/// interned symbol of the same name
+impl Render for Symbol {
+    fn render(&self, st: &SymTable, f: &mut fmt::Formatter, _style: Style) -> fmt::Result {
+        fmt::Display::fmt(&Symbol::display(*self, st), f)
+    }
+}
+
+impl Render for SExp {
+    fn render(&self, st: &SymTable, f: &mut fmt::Formatter, style: Style) -> fmt::Result {
+        match *self {
+            SExp::Sym(ref s, _) => s.render(st, f, style),
+            SExp::LString(ref s, _) => match style {
+                Style::Write => write_string(f, s),
+                Style::Display => f.write_str(s),
+            },
+            SExp::List(ref l, _) => render_list(st, f, l, style),
+            SExp::Num(ref n, _) => write!(f, "{}", n),
+            SExp::Boolean(b, _) => f.write_str(if b { "#t" } else { "#f" }),
+            SExp::Nil => f.write_str("()"),
+        }
+    }
+}
+
+impl Render for Fun {
+    fn render(&self, st: &SymTable, f: &mut fmt::Formatter, style: Style) -> fmt::Result {
+        match self.name() {
+            Some(name) => {
+                f.write_str("#<procedure ")?;
+                name.render(st, f, style)?;
+                f.write_str(">")
+            },
+            None => f.write_str("#<procedure>"),
+        }
+    }
+}
+
+impl Render for Data {
+    fn render(&self, st: &SymTable, f: &mut fmt::Formatter, style: Style) -> fmt::Result {
+        match *self {
+            Data::DExp(ref s) => s.render(st, f, style),
+            Data::DFun(ref fun) => fun.render(st, f, style),
+            Data::DList(ref l) => render_list(st, f, l, style),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Render;
+    use interpret::Data;
+    use number::Number;
+    use parse::Parser;
+    use parse::SExp;
+    use symtable::SymTable;
+
+    fn read(st: &SymTable, text: &str) -> SExp {
+        Parser::new(st, text.chars()).next().unwrap().unwrap()
+    }
+
+    #[test]
+    fn write_and_display() {
+        let st = SymTable::new();
+        let d = Data::from_sexp(&read(&st, "(a \"b \\\"c\\\"\\n\" (#t #f) () -1/2 2.0)"));
+        assert_eq!("(a \"b \\\"c\\\"\\n\" (#t #f) () -1/2 2.0)", d.write(&st).to_string());
+        assert_eq!("(a b \"c\"\n (#t #f) () -1/2 2.0)", d.display(&st).to_string());
+        assert_eq!("()", SExp::Nil.write(&st).to_string());
+    }
+
+    /// A xorshift generator, so that failures are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick(&mut self, chars: &[char]) -> char {
+            chars[self.below(chars.len())]
+        }
+    }
+
+    fn digits(rng: &mut Rng, n: usize) -> String {
+        (0..n).map(|_| (b'0' + rng.below(10) as u8) as char ).collect()
+    }
+
+    fn random_number(rng: &mut Rng) -> Number {
+        match rng.below(5) {
+            0 => Number::Int(rng.next() as i64),
+            1 => Number::Int(rng.below(200) as i64 - 100),
+            2 => {
+                let len = 19 + rng.below(30);
+                Number::parse(&format!("-{}", digits(rng, len))).unwrap()
+            },
+            3 => {
+                let den = 1 + rng.below(1000);
+                Number::parse(&format!("{}/{}", rng.next() as i32, den)).unwrap()
+            },
+            _ => {
+                let r = f64::from_bits(rng.next());
+                // NaN has many representations, but only one reads back
+                Number::Real(if r.is_nan() { f64::NAN } else { r })
+            },
+        }
+    }
+
+    fn random_sexp(rng: &mut Rng, st: &SymTable, depth: usize) -> SExp {
+        const SYM_START: &[char] = &['a', 'z', 'Q', '\u{e9}', '+', '-', '*', '/', '<', '=', '!', '?', '_', ':', '&', '%'];
+        const SYM_REST: &[char] = &['a', 'b', '0', '9', '-', '>', '.', '?', '!'];
+        const STRING: &[char] = &['a', ' ', '"', '\\', '\n', '\t', '\r', '(', ')', ';', '#', '\u{e9}', '\u{1f600}'];
+        match rng.below(if depth > 0 { 6 } else { 5 }) {
+            0 => SExp::Num(random_number(rng), None),
+            1 => {
+                let len = rng.below(8);
+                SExp::LString((0..len).map(|_| rng.pick(STRING) ).collect(), None)
+            },
+            2 => {
+                let mut name = rng.pick(SYM_START).to_string();
+                // a sign or '.' followed by a digit would read as a number
+                let len = if name == "+" || name == "-" { 0 } else { rng.below(5) };
+                for _ in 0..len {
+                    name.push(rng.pick(SYM_REST));
+                }
+                SExp::Sym(st.sym_for(&name), None)
+            },
+            3 => SExp::Boolean(rng.below(2) == 0, None),
+            4 => SExp::Num(Number::Int(rng.below(10) as i64), None),
+            _ => {
+                let len = rng.below(5);
+                SExp::List((0..len).map(|_| random_sexp(rng, st, depth - 1) ).collect(), None)
+            },
+        }
+    }
+
+    #[test]
+    fn write_round_trips() {
+        let st = SymTable::new();
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let x = random_sexp(&mut rng, &st, 4);
+            let text = x.write(&st).to_string();
+            assert_eq!(x, read(&st, &text), "reading back {}", text);
+            let d = Data::from_sexp(&x);
+            assert_eq!(text, d.write(&st).to_string());
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::cmp::Ordering;
+use std::fmt;
+use bigint::BigInt;
+
+/// An exact fraction, always in lowest terms with a positive denominator
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Rational {
+    num: BigInt,
+    den: BigInt,
+}
+
+fn gcd(a: &BigInt, b: &BigInt) -> BigInt {
+    let mut a = a.abs();
+    let mut b = b.abs();
+    while !b.is_zero() {
+        let (_, r) = a.divrem(&b).unwrap();
+        a = b;
+        b = r;
+    }
+    a
+}
+
+impl Rational {
+    /// `None` if `den` is zero
+    pub fn new(num: BigInt, den: BigInt) -> Option<Rational> {
+        if den.is_zero() {
+            return None;
+        }
+        let (num, den) = if den.is_negative() { (num.neg(), den.neg()) } else { (num, den) };
+        let g = gcd(&num, &den);
+        Some(Rational {
+            num: num.divrem(&g).unwrap().0,
+            den: den.divrem(&g).unwrap().0,
+        })
+    }
+
+    pub fn from_int(i: BigInt) -> Rational {
+        Rational {
+            num: i,
+            den: BigInt::from_i64(1),
+        }
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.num
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.den
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.den == BigInt::from_i64(1)
+    }
+
+    /// Both parts are scaled down by a power of two first, since either may be too large for an
+    /// `f64` even when their ratio is not
+    pub fn to_f64(&self) -> f64 {
+        let (num, num_exp) = self.num.to_f64_exp();
+        let (den, den_exp) = self.den.to_f64_exp();
+        let exp = num_exp - den_exp;
+        // applied in two steps, as 2^exp alone may overflow where the result does not
+        num / den * 2f64.powi(exp / 2) * 2f64.powi(exp - exp / 2)
+    }
+
+    pub fn add(&self, other: &Rational) -> Rational {
+        let num = self.num.mul(&other.den).add(&other.num.mul(&self.den));
+        Rational::new(num, self.den.mul(&other.den)).unwrap()
+    }
+
+    pub fn sub(&self, other: &Rational) -> Rational {
+        let num = self.num.mul(&other.den).sub(&other.num.mul(&self.den));
+        Rational::new(num, self.den.mul(&other.den)).unwrap()
+    }
+
+    pub fn mul(&self, other: &Rational) -> Rational {
+        Rational::new(self.num.mul(&other.num), self.den.mul(&other.den)).unwrap()
+    }
+
+    /// `None` if `other` is zero
+    pub fn div(&self, other: &Rational) -> Option<Rational> {
+        Rational::new(self.num.mul(&other.den), self.den.mul(&other.num))
+    }
+
+    /// Rounds towards zero
+    pub fn truncate(&self) -> BigInt {
+        self.num.divrem(&self.den).unwrap().0
+    }
+
+    /// Rounds towards negative infinity
+    pub fn floor(&self) -> BigInt {
+        let (q, r) = self.num.divrem(&self.den).unwrap();
+        if r.is_negative() {
+            q.sub(&BigInt::from_i64(1))
+        } else {
+            q
+        }
+    }
+
+    /// Rounds to the nearest integer, and to the even one when exactly half way between two
+    pub fn round(&self) -> BigInt {
+        let (q, r) = self.num.divrem(&self.den).unwrap();
+        let away = if self.num.is_negative() { BigInt::from_i64(-1) } else { BigInt::from_i64(1) };
+        let twice_r = r.abs().mul(&BigInt::from_i64(2));
+        match twice_r.cmp(&self.den) {
+            Ordering::Less => q,
+            Ordering::Greater => q.add(&away),
+            Ordering::Equal => {
+                let (_, parity) = q.divrem(&BigInt::from_i64(2)).unwrap();
+                if parity.is_zero() { q } else { q.add(&away) }
+            }
+        }
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        // denominators are positive, so cross-multiplying preserves the ordering
+        self.num.mul(&other.den).cmp(&other.num.mul(&self.den))
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rational;
+    use bigint::BigInt;
+
+    fn ratio(n: i64, d: i64) -> Rational {
+        Rational::new(BigInt::from_i64(n), BigInt::from_i64(d)).unwrap()
+    }
+
+    fn int(i: i64) -> BigInt {
+        BigInt::from_i64(i)
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!("1/3", ratio(2, 6).to_string());
+        assert_eq!("-1/3", ratio(2, -6).to_string());
+        assert_eq!("1/3", ratio(-2, -6).to_string());
+        assert_eq!("0/1", ratio(0, -5).to_string());
+        assert!(ratio(4, 2).is_integer());
+        assert!(Rational::new(int(1), int(0)).is_none());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(ratio(5, 6), ratio(1, 2).add(&ratio(1, 3)));
+        assert_eq!(ratio(1, 6), ratio(1, 2).sub(&ratio(1, 3)));
+        assert_eq!(ratio(1, 6), ratio(1, 2).mul(&ratio(1, 3)));
+        assert_eq!(Some(ratio(3, 2)), ratio(1, 2).div(&ratio(1, 3)));
+        assert_eq!(None, ratio(1, 2).div(&ratio(0, 1)));
+        assert!(ratio(1, 3) < ratio(1, 2));
+        assert!(ratio(-1, 2) < ratio(-1, 3));
+    }
+
+    #[test]
+    fn rounding() {
+        assert_eq!((int(-4), int(-3), int(-4)), (ratio(-7, 2).floor(), ratio(-7, 2).truncate(), ratio(-7, 2).round()));
+        assert_eq!((int(3), int(3), int(4)), (ratio(7, 2).floor(), ratio(7, 2).truncate(), ratio(7, 2).round()));
+        assert_eq!(int(2), ratio(5, 2).round());
+        assert_eq!(int(-2), ratio(-5, 2).round());
+        assert_eq!(int(1), ratio(4, 3).round());
+        assert_eq!(int(2), ratio(5, 3).round());
+        assert_eq!(int(-2), ratio(-5, 3).round());
+    }
+
+    #[test]
+    fn float_conversion() {
+        assert_eq!(-0.75, ratio(-3, 4).to_f64());
+        let fact = (1..=200).fold(int(1), |acc, i| acc.mul(&int(i)) );
+        assert!(fact.to_f64().is_infinite());
+        let near_one = Rational::new(fact.clone(), fact.add(&int(1))).unwrap();
+        assert_eq!(1.0, near_one.to_f64());
+        let tiny = Rational::new(int(1), fact.clone()).unwrap();
+        assert_eq!(0.0, tiny.to_f64());
+        let huge = Rational::new(fact.mul(&fact), fact.add(&int(1))).unwrap();
+        assert!(huge.to_f64().is_infinite());
+        let big = Rational::new(fact.mul(&int(3)), fact.mul(&int(2)).add(&int(1))).unwrap();
+        assert!((big.to_f64() - 1.5).abs() < 1e-15);
+    }
+}
@@ -0,0 +1,184 @@
+//! The interactive read-eval-print loop, run when `bml` is given no script
+
+use std::env;
+use std::path::PathBuf;
+use rustyline::Context;
+use rustyline::Editor;
+use rustyline::Helper;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use bml::interpret::Interpreter;
+use bml::parse::ParseError;
+use bml::parse::ParseErrorKind;
+use bml::parse::Parser;
+use bml::parse::SExp;
+use bml::parse::is_atom_char;
+use bml::print::Render;
+use bml::symtable::SymTable;
+
+const PROMPT: &str = "bml> ";
+/// Shown while the lines entered so far do not yet make a complete expression
+const CONTINUATION_PROMPT: &str = "...> ";
+
+/// `~/.bml_history`, if we know where home is
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".bml_history") )
+}
+
+/// Completes symbol names from those interned so far, which includes all the builtins
+struct SymbolCompleter<'a> {
+    st: &'a SymTable,
+}
+
+impl<'a> SymbolCompleter<'a> {
+    /// Where the symbol ending at `pos` starts, and the names it might be completed to
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line[..pos].char_indices().rev()
+            .find(|&(_, c)| !is_atom_char(c) )
+            .map_or(0, |(i, c)| i + c.len_utf8() );
+        let prefix = &line[start..pos];
+        let mut names: Vec<String> = self.st.iter()
+            .map(|s| self.st.name(s).to_string() )
+            .filter(|name| name.starts_with(prefix) )
+            .collect();
+        names.sort();
+        (start, names)
+    }
+}
+
+impl<'a> Completer for SymbolCompleter<'a> {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl<'a> Hinter for SymbolCompleter<'a> {
+    type Hint = String;
+}
+impl<'a> Highlighter for SymbolCompleter<'a> {
+}
+impl<'a> Validator for SymbolCompleter<'a> {
+}
+impl<'a> Helper for SymbolCompleter<'a> {
+}
+
+/// Parses the lines entered so far, giving `None` if they end part way through an expression
+/// so that the user needs to enter more
+fn parse_input(st: &SymTable, text: &str) -> Option<Result<Vec<SExp>, ParseError>> {
+    match Parser::new(st, text.chars()).collect() {
+        Err(ParseError{kind: ParseErrorKind::Incomplete, ..}) => None,
+        result => Some(result),
+    }
+}
+
+/// Evaluates the given forms in turn, printing the results
+fn eval_forms(interpreter: &Interpreter, forms: &[SExp]) {
+    for form in forms {
+        match interpreter.eval_sexp(form) {
+            Ok(d) => println!("{}", d.write(interpreter.symbols())),
+            Err(e) => {
+                println!("error: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Reads and evaluates expressions from the terminal until end of input.  Definitions made by
+/// one input remain visible to later ones, and errors are reported without ending the session.
+pub fn run(st: &SymTable, interpreter: &Interpreter) {
+    let mut editor: Editor<SymbolCompleter, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("unable to start the REPL: {}", e);
+            return;
+        }
+    };
+    editor.set_helper(Some(SymbolCompleter { st }));
+    let history = history_path();
+    if let Some(ref path) = history {
+        // there is no history file until the first session ends
+        let _ = editor.load_history(path);
+    }
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            },
+            // Ctrl-C abandons whatever has been entered so far
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            },
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        }
+        let forms = match parse_input(st, &input) {
+            Some(forms) => forms,
+            None => continue,
+        };
+        let entry = input.trim();
+        if !entry.is_empty() {
+            let _ = editor.add_history_entry(entry);
+        }
+        input.clear();
+        match forms {
+            Ok(forms) => eval_forms(interpreter, &forms),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+    if let Some(ref path) = history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("unable to save history to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_input;
+    use super::SymbolCompleter;
+    use bml::symtable::SymTable;
+
+    #[test]
+    fn completion() {
+        let st = SymTable::new();
+        for name in &["exact->inexact", "exit", "floor", "\u{e9}t\u{e9}"] {
+            st.sym_for(name);
+        }
+        let completer = SymbolCompleter { st: &st };
+        let expected = (1, vec!("exact->inexact".to_string(), "exit".to_string()));
+        assert_eq!(expected, completer.candidates("(ex 1)", 3));
+        assert_eq!((1, vec!()), completer.candidates("(zz", 3));
+        assert_eq!(7, completer.candidates("(\u{e9}t\u{e9} \u{e9}", 9).0);
+    }
+
+    #[test]
+    fn complete_input() {
+        let st = SymTable::new();
+        for text in &["", "1", "(+ 1 2)", "(a (b) \"(\")\n", "'x", ",@x", "(a) ; (b", "#| ( |# 1", "#;x y"] {
+            assert!(parse_input(&st, text).unwrap().is_ok(), "{:?} should be complete", text);
+        }
+        assert!(parse_input(&st, ")").unwrap().is_err());
+    }
+
+    #[test]
+    fn incomplete_input() {
+        let st = SymTable::new();
+        for text in &["(", "(+ 1\n", "((a) (b)", "\"abc", "\"a\\\"", "'", "`(1 ,", "#| a #| b |# c", "(; )\n", "#;", "1 #;\n"] {
+            assert!(parse_input(&st, text).is_none(), "{:?} should be incomplete", text);
+        }
+    }
+}
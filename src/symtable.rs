@@ -1,58 +1,228 @@
-use std::cell::RefCell;
-use std::rc::Rc;
 use std::collections::HashMap;
-use std::hash::Hash;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::BuildHasherDefault;
 use std::hash::Hasher;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 
-#[derive(Debug)]
-pub struct Symbol(String);
+/// A symbol is a number identifying an entry in the `SymTable` which created it, so symbols are
+/// cheap to copy, compare and hash.  Ids are allocated from 0 by each table, so symbols from
+/// different tables must not be mixed, and a symbol's name is only available from its own table.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord)]
+pub struct Symbol(u32);
 
-pub type SymbolRef = Rc<Symbol>;
+impl Symbol {
+    /// The symbol's index within its table, which is the same each time a program interns the
+    /// same names in the same order
+    pub fn id(self) -> u32 {
+        self.0
+    }
 
-pub struct SymTable {
-    name_to_sym:RefCell<HashMap<String, SymbolRef>>,
+    /// The symbol's name, as held by `st`, the table it came from
+    pub fn name(self, st: &SymTable) -> Arc<str> {
+        st.name(self)
+    }
+
+    /// Shows the symbol as named in `st`, the table it came from
+    pub fn display(self, st: &SymTable) -> SymbolDisplay<'_> {
+        SymbolDisplay { sym: self, st }
+    }
+}
+
+/// A symbol together with its table, so that it can be shown with `{}`
+pub struct SymbolDisplay<'a> {
+    sym: Symbol,
+    st: &'a SymTable,
 }
 
-impl PartialEq for Symbol {
-    fn eq(&self, other: &Symbol) -> bool {
-        self as *const _ == other as *const _
+/// Uninterned symbols are shown with a `#:` prefix, so that they can be told apart from the
+/// interned symbol of the same name
+impl<'a> fmt::Display for SymbolDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.st.is_interned(self.sym) {
+            f.write_str("#:")?;
+        }
+        f.write_str(&self.st.name(self.sym))
     }
 }
-impl Eq for Symbol {
+
+struct Entry {
+    name: Arc<str>,
+    /// false for symbols which `SymTable::sym_for()` will never give, however they are named
+    interned: bool,
+    keyword: bool,
+}
+
+#[derive(Default)]
+struct Names {
+    /// Indexed by `Symbol` id.  Entries are never removed, so that a symbol remains valid for
+    /// the life of its table.
+    entries: Vec<Entry>,
+    /// The interned symbols, sharing their names with `entries`
+    by_name: HashMap<Arc<str>, Symbol>,
 }
-impl Hash for Symbol {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write_usize(self as *const _ as usize);
+
+impl Names {
+    fn add(&mut self, name: &str, interned: bool) -> Symbol {
+        let sym = Symbol(u32::try_from(self.entries.len()).expect("too many symbols"));
+        let name: Arc<str> = Arc::from(name);
+        if interned {
+            self.by_name.insert(name.clone(), sym);
+        }
+        let keyword = name.len() > 1 && name.ends_with(':');
+        self.entries.push(Entry { name, interned, keyword });
+        sym
+    }
+}
+
+// No operation on the locked collections here can panic part way through an update, so a lock
+// poisoned by a panic elsewhere still guards consistent data, and can be used regardless
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner() )
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner() )
+}
+
+/// A `Hasher` for maps keyed by `Symbol`.  Symbol ids are small sequential numbers rather than
+/// anything an attacker might choose, so there is no need for the default hasher's resistance to
+/// collision attacks, only for the ids to be spread over the hash bits.
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.write_u64(u64::from(*b));
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(u64::from(i));
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        // Fibonacci hashing, multiplying by 2^64 divided by the golden ratio
+        self.0 = (self.0.rotate_left(5) ^ i).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+/// Clones of a table share the same symbols, so that a clone can be kept by code which needs to
+/// create symbols later on, including code running on other threads
+#[derive(Clone)]
+pub struct SymTable {
+    names:Arc<RwLock<Names>>,
+    gensym_count:Arc<AtomicU64>,
+}
+
+impl Default for SymTable {
+    fn default() -> SymTable {
+        SymTable::new()
     }
 }
 
 impl SymTable {
     pub fn new() -> SymTable {
         SymTable {
-            name_to_sym: RefCell::new(HashMap::new()),
+            names: Arc::new(RwLock::new(Names::default())),
+            gensym_count: Arc::new(AtomicU64::new(0)),
         }
     }
-    pub fn insert(&self, name:&str) -> Option<SymbolRef> {
-        if self.name_to_sym.borrow().contains_key(name) {
+
+    pub fn insert(&self, name:&str) -> Option<Symbol> {
+        let mut names = write(&self.names);
+        if names.by_name.contains_key(name) {
             None
         } else {
-            let sym = Rc::new(Symbol(name.to_string()));
-            self.name_to_sym.borrow_mut().insert(name.to_string(), sym);
-            self.name_to_sym.borrow().get(name).map(|s| s.clone() )
+            Some(names.add(name, true))
         }
     }
 
-    pub fn sym_for(&self, name:&str) -> SymbolRef {
+    pub fn sym_for(&self, name:&str) -> Symbol {
+        // most lookups are of symbols which already exist, needing only the shared lock
+        if let Some(sym) = read(&self.names).by_name.get(name) {
+            return *sym;
+        }
         match self.insert(name) {
-            Some(s) => s,
-            None => self.name_to_sym.borrow().get(name).unwrap().clone()
+            Some(sym) => sym,
+            // another thread got there first
+            None => read(&self.names).by_name[name],
         }
     }
+
+    /// A new symbol which is distinct from every other, including any other with the same name
+    pub fn uninterned(&self, name:&str) -> Symbol {
+        write(&self.names).add(name, false)
+    }
+
+    /// A new uninterned symbol, named by appending a number to `prefix` which differs from that
+    /// of any earlier gensym from this table
+    pub fn gensym(&self, prefix:&str) -> Symbol {
+        let n = self.gensym_count.fetch_add(1, Ordering::Relaxed) + 1;
+        self.uninterned(&format!("{}{}", prefix, n))
+    }
+
+    /// The name of a symbol created by this table.  Panics given a symbol from some other table
+    /// which this one has no entry for.
+    pub fn name(&self, sym:Symbol) -> Arc<str> {
+        read(&self.names).entries[sym.0 as usize].name.clone()
+    }
+
+    pub fn is_interned(&self, sym:Symbol) -> bool {
+        read(&self.names).entries[sym.0 as usize].interned
+    }
+
+    /// Keywords, which name the arguments for `#!key` params, are symbols ending in ':'.  They
+    /// evaluate to themselves, and can't be bound as variables.
+    pub fn is_keyword(&self, sym:Symbol) -> bool {
+        read(&self.names).entries[sym.0 as usize].keyword
+    }
+
+    /// The symbol of this table with the given `id()`, if there is one
+    pub fn from_id(&self, id:u32) -> Option<Symbol> {
+        if (id as usize) < read(&self.names).entries.len() {
+            Some(Symbol(id))
+        } else {
+            None
+        }
+    }
+
+    /// The number of symbols interned so far
+    pub fn len(&self) -> usize {
+        read(&self.names).by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The symbols interned so far, in no particular order.  This is a snapshot, so symbols
+    /// interned while iterating are not included.
+    pub fn iter(&self) -> ::std::vec::IntoIter<Symbol> {
+        read(&self.names).by_name.values().copied().collect::<Vec<_>>().into_iter()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::SymTable;
+    use super::Symbol;
+    use super::SymbolMap;
+    use parse::SExp;
+    use print::Render;
+    use std::thread;
 
     #[test]
     fn it_works() {
@@ -62,9 +232,109 @@ mod tests {
         assert_eq!(foo, st.sym_for("foo"));
         assert_eq!(bar, st.sym_for("bar"));
         assert_ne!(foo, st.sym_for("bar"));
+    }
+
+    #[test]
+    fn names() {
+        let st = SymTable::new();
+        assert!(st.is_empty());
+        let sym = st.sym_for("string->number");
+        assert_eq!("string->number", &*st.name(sym));
+        assert_eq!("string->number", sym.write(&st).to_string());
+        st.sym_for("b");
+        st.sym_for("b");
+        assert_eq!(2, st.len());
+        let mut names: Vec<String> = st.iter().map(|s| st.name(s).to_string() ).collect();
+        names.sort();
+        assert_eq!(vec!("b", "string->number"), names);
+    }
+
+    #[test]
+    fn keywords() {
+        let st = SymTable::new();
+        assert!(st.is_keyword(st.sym_for("key:")));
+        assert!(!st.is_keyword(st.sym_for("key")));
+        assert!(!st.is_keyword(st.sym_for(":")));
+        assert!(!st.is_keyword(st.sym_for(":key")));
+    }
 
-        let st2 = SymTable::new();
-        assert_ne!(foo, st2.sym_for("foo"));
+    #[test]
+    fn display() {
+        let st = SymTable::new();
+        let sym = st.sym_for("lambda");
+        assert_eq!("lambda", &*sym.name(&st));
+        assert_eq!("lambda", sym.display(&st).to_string());
+        let u = st.uninterned("lambda");
+        assert_eq!("lambda", &*u.name(&st));
+        assert_eq!("#:lambda", format!("{}", u.display(&st)));
+    }
+
+    #[test]
+    fn uninterned() {
+        let st = SymTable::new();
+        let g1 = st.gensym("g");
+        let g2 = st.clone().gensym("g");
+        assert_eq!("#:g1", g1.write(&st).to_string());
+        assert_eq!("g2", &*st.name(g2));
+        assert_ne!(g1, st.sym_for("g1"));
+        assert_ne!(st.uninterned("x"), st.uninterned("x"));
+        assert_eq!("#:x", st.uninterned("x").write(&st).to_string());
+        assert_eq!("x", st.sym_for("x").write(&st).to_string());
+        assert_eq!(vec!("g1", "x"), {
+            let mut names: Vec<String> = st.iter().map(|s| st.name(s).to_string() ).collect();
+            names.sort();
+            names
+        });
+    }
+
+    #[test]
+    fn ids() {
+        let st = SymTable::new();
+        let foo = st.sym_for("foo");
+        let copy = foo;
+        assert_eq!(Some(foo), st.from_id(copy.id()));
+        assert_eq!("foo", &*st.name(st.from_id(foo.id()).unwrap()));
+        assert_eq!(None, st.from_id(u32::MAX));
+
+        // each table numbers its own symbols, and knows nothing of the ids of others
+        let other = SymTable::new();
+        st.sym_for("bar");
+        let bar = other.sym_for("bar");
+        assert_eq!(Some(bar), other.from_id(0));
+        assert_eq!("bar", &*other.name(bar));
+        assert_eq!(None, other.from_id(1));
+
+        let mut map = SymbolMap::default();
+        let syms: Vec<Symbol> = (0..1000).map(|i| st.sym_for(&format!("v{}", i)) ).collect();
+        for (i, s) in syms.iter().enumerate() {
+            map.insert(*s, i);
+        }
+        for (i, s) in syms.iter().enumerate() {
+            assert_eq!(Some(&i), map.get(s));
+        }
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {
     }
 
+    #[test]
+    fn threads() {
+        assert_send_sync::<SymTable>();
+        assert_send_sync::<Symbol>();
+        assert_send_sync::<SExp>();
+
+        let st = SymTable::new();
+        let handles: Vec<_> = (0..4).map(|t| {
+            let st = st.clone();
+            thread::spawn(move || {
+                (0..100).map(|i| st.sym_for(&format!("s{}", (i * (t + 1)) % 50)) ).collect::<Vec<_>>()
+            })
+        }).collect();
+        for h in handles {
+            for sym in h.join().unwrap() {
+                assert_eq!(sym, st.sym_for(&st.name(sym)));
+            }
+        }
+        assert_eq!(50, st.len());
+    }
 }